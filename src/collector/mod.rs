@@ -1,16 +1,22 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
 use bytes::Bytes;
 use futures::StreamExt;
 use log::warn;
 use metrics::{gauge, increment_counter};
+use tokio::time::Instant;
 
 use crate::parsers::{self, Parser};
 use crate::pipeline_stages::{PipelineError, Service};
 use crate::targets;
 
-pub async fn collect(metrics: &[Metric]) {
+pub async fn collect(metrics: &[Metric], deadline: Option<Instant>) {
     futures::stream::iter(metrics)
-        .for_each_concurrent(25, |m| async {
-            match m.collect().await {
+        .for_each_concurrent(25, |m| async move {
+            match m.collect(deadline).await {
                 Ok(()) => {
                     increment_counter!(crate::COLLECT_SUCCESSES, "metric" => m.name.clone());
                 }
@@ -148,10 +154,14 @@ where
             targets: self.targets,
             parser: Box::new(self.parser),
             pipeline_stages: Box::new(self.pipeline_stages),
+            parsed: Mutex::new(HashMap::new()),
         }
     }
 }
 
+// index of the target, and `target` and additional labels of the fetched data
+type Key = (usize, String, Vec<(String, String)>);
+
 pub struct Metric {
     pub name: String,
     pub help: String,
@@ -159,42 +169,214 @@ pub struct Metric {
     pub targets: Vec<targets::Target>,
     pub parser: Box<dyn Parser + Send + Sync>,
     pub pipeline_stages: Box<dyn Service<Error = PipelineError> + Send + Sync>,
+    parsed: Mutex<HashMap<Key, Vec<parsers::Parsed>>>,
 }
 
 impl Metric {
-    async fn collect(&self) -> Result<(), CollectError> {
-        for target in &self.targets {
-            let resp = target.fetch().await?;
-            let resp = self
-                .pipeline_stages
-                .call(resp)
-                .map_err(CollectError::TransformerError)?;
-
-            for parsed in self.parser.parse(resp)? {
-                let mut labels: Vec<metrics::Label> = parsed
-                    .labels
-                    .into_iter()
-                    .map(|(k, v)| metrics::Label::from(&(k, v)))
-                    .collect();
-
-                labels.push(metrics::Label::from(&(
-                    "target".to_owned(),
-                    target.describe().to_owned(),
-                )));
-
-                labels.sort();
-
-                let value = match (parsed.value, self.value) {
-                    (Some(value), _) | (_, Some(value)) => Ok(value),
-                    (None, None) => Err(CollectError::MissingValue(String::from(
-                        "expected either a constant or a parsed value",
-                    ))),
-                }?;
-
-                gauge!(self.name.clone(), value, labels);
+    async fn collect(&self, deadline: Option<Instant>) -> Result<(), CollectError> {
+        let mut seen = HashSet::new();
+
+        for (index, target) in self.targets.iter().enumerate() {
+            let keep = target.reports_unchanged();
+
+            for fetched in target.fetch(deadline).await? {
+                let key = (index, fetched.target.clone(), fetched.labels.clone());
+                let parsed = self.parse(&key, fetched.data, fetched.unchanged, keep)?;
+
+                for parsed in parsed {
+                    let mut labels: Vec<metrics::Label> = parsed
+                        .labels
+                        .into_iter()
+                        .chain(fetched.labels.iter().cloned())
+                        .map(|(k, v)| metrics::Label::from(&(k, v)))
+                        .collect();
+
+                    labels.push(metrics::Label::from(&(
+                        "target".to_owned(),
+                        fetched.target.clone(),
+                    )));
+
+                    labels.sort();
+
+                    let value = match (parsed.value, self.value) {
+                        (Some(value), _) | (_, Some(value)) => Ok(value),
+                        (None, None) => Err(CollectError::MissingValue(String::from(
+                            "expected either a constant or a parsed value",
+                        ))),
+                    }?;
+
+                    gauge!(self.name.clone(), value, labels);
+                }
+
+                seen.insert(key);
             }
+
+            target.commit().await?;
         }
 
+        // forget data that was not fetched this time
+        self.parsed
+            .lock()
+            .unwrap()
+            .retain(|key, _| seen.contains(key));
+
         Ok(())
     }
+
+    /// Runs the pipeline and the parser on `data`, or reuses the result from
+    /// the previous scrape if the target reported the data as unchanged. The
+    /// result is only kept for the next scrape if `keep` is set.
+    fn parse(
+        &self,
+        key: &Key,
+        data: Bytes,
+        unchanged: bool,
+        keep: bool,
+    ) -> Result<Vec<parsers::Parsed>, CollectError> {
+        if unchanged {
+            if let Some(parsed) = self.parsed.lock().unwrap().get(key) {
+                return Ok(parsed.clone());
+            }
+        }
+
+        let transformed = self
+            .pipeline_stages
+            .call(data)
+            .map_err(CollectError::TransformerError)?;
+        let parsed = self.parser.parse(transformed)?;
+
+        if keep {
+            self.parsed
+                .lock()
+                .unwrap()
+                .insert(key.clone(), parsed.clone());
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{
+        parsers::json::JsonParser,
+        pipeline_stages::{JqStage, Pipeline},
+        targets::testing::TempDir,
+    };
+
+    struct CountingStage(Arc<AtomicU32>);
+    impl Service for CountingStage {
+        type Error = PipelineError;
+
+        fn call(&self, input: Bytes) -> Result<Bytes, Self::Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(input)
+        }
+    }
+
+    // Records the values found by the wrapped parser.
+    struct RecordingParser(JsonParser, Arc<Mutex<Vec<f64>>>);
+    impl Parser for RecordingParser {
+        fn parse(&self, data: Bytes) -> Result<Vec<parsers::Parsed>, parsers::ParseError> {
+            let parsed = self.0.parse(data)?;
+            let mut values = self.1.lock().unwrap();
+            values.extend(parsed.iter().filter_map(|p| p.value));
+            Ok(parsed)
+        }
+    }
+
+    fn key(topic: &str) -> Key {
+        (
+            0,
+            "target".to_owned(),
+            vec![("topic".to_owned(), topic.to_owned())],
+        )
+    }
+
+    fn metric(calls: &Arc<AtomicU32>) -> Metric {
+        MetricBuilder::new("metric".into(), "help".into())
+            .pipeline_stages(CountingStage(calls.clone()))
+            .parser(JsonParser::new(Vec::new(), Some("val".into())))
+            .build()
+    }
+
+    #[test]
+    fn test_parse_reuses_result_for_unchanged_data() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let metric = metric(&calls);
+
+        let parsed = metric.parse(&key("a"), r#"{"val": 1}"#.into(), false, true);
+        assert_eq!(parsed.unwrap()[0].value, Some(1f64));
+
+        let parsed = metric.parse(&key("a"), Bytes::new(), true, true);
+        assert_eq!(parsed.unwrap()[0].value, Some(1f64));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // identical data is parsed again unless the target says it is unchanged
+        let parsed = metric.parse(&key("a"), r#"{"val": 1}"#.into(), false, true);
+        assert_eq!(parsed.unwrap()[0].value, Some(1f64));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_parse_keys_include_labels() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let metric = metric(&calls);
+
+        metric
+            .parse(&key("a"), r#"{"val": 1}"#.into(), false, true)
+            .unwrap();
+        metric
+            .parse(&key("b"), r#"{"val": 2}"#.into(), false, true)
+            .unwrap();
+
+        let parsed = metric.parse(&key("a"), Bytes::new(), true, true);
+        assert_eq!(parsed.unwrap()[0].value, Some(1f64));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_parse_keeps_nothing_unless_asked() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let metric = metric(&calls);
+
+        metric
+            .parse(&key("a"), r#"{"val": 1}"#.into(), false, false)
+            .unwrap();
+
+        assert!(metric.parsed.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_collect_counts_new_tail_lines() {
+        let log = TempDir::new("collect-tail");
+        log.write("app.log", "error 1\ninfo\nerror 2\n");
+
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let metric = MetricBuilder::new("errors".into(), "help".into())
+            .targets(vec![targets::Target::Tail(Box::new(
+                targets::tail::Config::new(log.path("app.log")),
+            ))])
+            .pipeline_stages(JqStage::new(
+                Pipeline,
+                r#"{errors: map(select(startswith("error"))) | length}"#.into(),
+            ))
+            .parser(RecordingParser(
+                JsonParser::new(Vec::new(), Some("errors".into())),
+                values.clone(),
+            ))
+            .build();
+
+        metric.collect(None).await.unwrap();
+        log.write("app.log", "error 1\ninfo\nerror 2\nerror 3\n");
+        metric.collect(None).await.unwrap();
+
+        assert_eq!(*values.lock().unwrap(), vec![2.0, 1.0]);
+    }
 }
@@ -7,11 +7,14 @@ pub mod targets;
 
 use std::sync::Arc;
 
+use bytes::Bytes;
 use collector::collect;
 use metrics::{describe_counter, describe_gauge, register_counter};
+use tokio::time::Instant;
 
 const COLLECT_FAILURES: &str = "data_exporter_collect_failures_total";
 const COLLECT_SUCCESSES: &str = "data_exporter_collect_successes_total";
+const TARGET_HTTP_STATUS_CODE: &str = "data_exporter_target_http_status_code";
 
 pub fn init_metrics(metrics: &DataMetrics) {
     let metrics = metrics.metrics.clone();
@@ -24,6 +27,10 @@ pub fn init_metrics(metrics: &DataMetrics) {
 
     describe_counter!(COLLECT_FAILURES, "Number of failed collects");
     describe_counter!(COLLECT_SUCCESSES, "Number of succeeded collects");
+    describe_gauge!(
+        TARGET_HTTP_STATUS_CODE,
+        "Status code of the last response from a http target"
+    );
 }
 
 #[derive(Clone)]
@@ -38,8 +45,52 @@ impl DataMetrics {
         }
     }
 
-    pub async fn collect(&self) {
+    /// Collects all metrics, `deadline` is when the scrape is considered
+    /// failed by the caller and bounds how long targets keep retrying.
+    pub async fn collect(&self, deadline: Option<Instant>) {
         let metrics: Arc<Vec<collector::Metric>> = self.metrics.clone();
-        collect(&metrics).await;
+        collect(&metrics, deadline).await;
+    }
+
+    /// Starts the background connections of all stream and mqtt targets,
+    /// otherwise they are started by the first collect.
+    pub fn start_background_targets(&self) {
+        for target in self.metrics.iter().flat_map(|m| m.targets.iter()) {
+            match target {
+                targets::Target::Stream(stream) => stream.start(),
+                targets::Target::Mqtt(mqtt) => mqtt.start(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the largest `max_body_size` of the push targets, or `None` if
+    /// there are none.
+    pub fn max_push_body_size(&self) -> Option<u64> {
+        self.metrics
+            .iter()
+            .flat_map(|m| m.targets.iter())
+            .filter_map(|target| match target {
+                targets::Target::Push(push) => push.max_body_size,
+                _ => None,
+            })
+            .max()
+    }
+
+    /// Stores `data` in all push targets named `name`, and returns whether
+    /// there was any.
+    pub fn push(&self, name: &str, data: Bytes) -> Result<bool, targets::TargetError> {
+        let mut found = false;
+
+        for target in self.metrics.iter().flat_map(|m| m.targets.iter()) {
+            if let targets::Target::Push(push) = target {
+                if push.name == name {
+                    push.push(data.clone())?;
+                    found = true;
+                }
+            }
+        }
+
+        Ok(found)
     }
 }
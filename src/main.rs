@@ -1,8 +1,15 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
-use axum::{routing::get, Extension, Router};
+use axum::{
+    extract::{DefaultBodyLimit, Path},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Extension, Router,
+};
+use bytes::Bytes;
 use clap::Parser;
 use data_exporter::log_filter::LogFilter;
+use data_exporter::targets::push::DEFAULT_MAX_BODY_SIZE;
 use data_exporter::DataMetrics;
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use tower_http::trace::TraceLayer;
@@ -39,10 +46,23 @@ async fn main() {
 
     let metrics = data_exporter::config::parse(opts.config).unwrap();
     data_exporter::init_metrics(&metrics);
+    metrics.start_background_targets();
+
+    // the push targets check their own limit, this only needs to let the
+    // largest bodies through
+    let push_limit = metrics
+        .max_push_body_size()
+        .unwrap_or(DEFAULT_MAX_BODY_SIZE);
 
     let app = Router::new()
         .route("/healthz", get(healthz))
         .route("/metrics", get(collect_metrics))
+        .route(
+            "/push/:name",
+            post(push).layer(DefaultBodyLimit::max(
+                usize::try_from(push_limit).unwrap_or(usize::MAX),
+            )),
+        )
         .layer(Extension(metrics))
         .layer(Extension(prometheus_handler))
         .layer(TraceLayer::new_for_http());
@@ -59,9 +79,37 @@ async fn healthz() -> &'static str {
 }
 
 async fn collect_metrics(
+    headers: HeaderMap,
     metrics: Extension<DataMetrics>,
     prometheus_handler: Extension<PrometheusHandle>,
 ) -> String {
-    metrics.collect().await;
+    metrics.collect(scrape_deadline(&headers)).await;
     prometheus_handler.render()
 }
+
+async fn push(
+    Path(name): Path<String>,
+    metrics: Extension<DataMetrics>,
+    body: Bytes,
+) -> StatusCode {
+    match metrics.push(&name, body) {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::PAYLOAD_TOO_LARGE,
+    }
+}
+
+// Prometheus announces how long it waits for a scrape before giving up,
+// targets are stopped a bit earlier so that the response still arrives in time
+fn scrape_deadline(headers: &HeaderMap) -> Option<tokio::time::Instant> {
+    let timeout = headers
+        .get("X-Prometheus-Scrape-Timeout-Seconds")?
+        .to_str()
+        .ok()?
+        .parse::<f64>()
+        .ok()?;
+    let timeout = Duration::try_from_secs_f64(timeout).ok()?;
+    let margin = (timeout / 10).min(Duration::from_millis(500));
+
+    Some(tokio::time::Instant::now() + timeout - margin)
+}
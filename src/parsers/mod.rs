@@ -32,6 +32,7 @@ impl From<ParseFloatError> for ParseError {
     }
 }
 
+#[derive(Clone)]
 pub struct Parsed {
     pub value: Option<f64>,
     pub labels: HashMap<String, String>,
@@ -0,0 +1,121 @@
+use async_compression::tokio::bufread::{BzDecoder, GzipDecoder, XzDecoder, ZstdDecoder};
+use bytes::Bytes;
+
+use super::{limit::LimitedBuffer, TargetError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Detect the compression from the magic bytes at the start of the data.
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            // the block or end of stream magic follows the block size
+            [b'B', b'Z', b'h', b'1'..=b'9', 0x31 | 0x17, ..] => Self::Bzip2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Self::Xz,
+            _ => Self::None,
+        }
+    }
+
+    /// Decompresses `data`, failing if the decompressed data is larger than
+    /// `limit`.
+    pub async fn decompress(self, data: Bytes, limit: Option<u64>) -> Result<Bytes, TargetError> {
+        let compression = match self {
+            Self::Auto => Self::detect(&data),
+            compression => compression,
+        };
+
+        let buffer = LimitedBuffer::new(limit);
+        match compression {
+            Self::Auto | Self::None => Ok(data),
+            Self::Gzip => {
+                let mut decoder = GzipDecoder::new(data.as_ref());
+                decoder.multiple_members(true);
+                buffer.read_from(decoder).await
+            }
+            Self::Zstd => buffer.read_from(ZstdDecoder::new(data.as_ref())).await,
+            Self::Bzip2 => buffer.read_from(BzDecoder::new(data.as_ref())).await,
+            Self::Xz => buffer.read_from(XzDecoder::new(data.as_ref())).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::write::{BzEncoder, GzipEncoder, XzEncoder, ZstdEncoder};
+    use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+    use super::*;
+
+    const DATA: &str = r#"{"jobs": 3, "failed": 1}"#;
+
+    async fn compress<W>(mut encoder: W, into: impl FnOnce(W) -> Vec<u8>) -> Bytes
+    where
+        W: AsyncWrite + Unpin,
+    {
+        encoder.write_all(DATA.as_bytes()).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        Bytes::from(into(encoder))
+    }
+
+    #[tokio::test]
+    async fn test_detects_compression() {
+        let compressed = [
+            compress(GzipEncoder::new(Vec::new()), GzipEncoder::into_inner).await,
+            compress(ZstdEncoder::new(Vec::new()), ZstdEncoder::into_inner).await,
+            compress(BzEncoder::new(Vec::new()), BzEncoder::into_inner).await,
+            compress(XzEncoder::new(Vec::new()), XzEncoder::into_inner).await,
+        ];
+
+        for data in compressed {
+            assert_eq!(
+                Compression::Auto.decompress(data, None).await.unwrap(),
+                DATA
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_uncompressed_data_is_unchanged() {
+        let data = Bytes::from(DATA);
+
+        assert_eq!(
+            Compression::Auto.decompress(data, None).await.unwrap(),
+            DATA
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explicit_compression() {
+        let data = compress(GzipEncoder::new(Vec::new()), GzipEncoder::into_inner).await;
+
+        assert_eq!(
+            Compression::None
+                .decompress(data.clone(), None)
+                .await
+                .unwrap(),
+            data
+        );
+        assert!(Compression::Zstd.decompress(data, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limits_decompressed_size() {
+        let data = compress(GzipEncoder::new(Vec::new()), GzipEncoder::into_inner).await;
+
+        assert!(matches!(
+            Compression::Auto.decompress(data, Some(10)).await,
+            Err(TargetError::BodyTooLarge(10))
+        ));
+    }
+}
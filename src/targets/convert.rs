@@ -0,0 +1,33 @@
+use serde_json::Value;
+
+/// Converts binary data, e.g. a blob column, to a JSON string, since none of
+/// the parsers handle binary data.
+pub fn text(data: &[u8]) -> Value {
+    Value::String(String::from_utf8_lossy(data).into_owned())
+}
+
+/// Converts text that looks like a number to a JSON number, so that parsers
+/// can use it as the value of a metric. Any other text stays a string.
+pub fn number(text: String) -> Value {
+    match text.parse::<i64>() {
+        Ok(i) => Value::from(i),
+        Err(_) => text
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Value::String(text), Value::Number),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number("42".to_owned()), Value::from(42));
+        assert_eq!(number("12.50".to_owned()), Value::from(12.5));
+        assert_eq!(number("NaN".to_owned()), Value::from("NaN"));
+        assert_eq!(number("ready".to_owned()), Value::from("ready"));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use bytes::Bytes;
+use serde_json::json;
+use tracing::warn;
+use walkdir::WalkDir;
+
+use super::{limit::LimitedJsonArray, TargetError};
+
+#[derive(Debug)]
+pub struct Config {
+    pub path: String,
+    /// How deep to walk below `path`, 1 only lists its direct entries.
+    pub max_depth: Option<usize>,
+    /// Only entries whose path relative to `path` matches are listed,
+    /// directories are still walked.
+    pub pattern: Option<glob::Pattern>,
+    pub max_body_size: Option<u64>,
+}
+
+impl Config {
+    pub fn new(path: String) -> Self {
+        Config {
+            path,
+            max_depth: None,
+            pattern: None,
+            max_body_size: None,
+        }
+    }
+
+    /// Walks the directory and returns a JSON array with the name, size,
+    /// modification time, type and permissions of each entry. Symlinks are
+    /// not followed, and entries that can not be read are skipped.
+    pub async fn fetch(&self) -> Result<Bytes, TargetError> {
+        let root = PathBuf::from(&self.path);
+        let max_depth = self.max_depth.unwrap_or(usize::MAX);
+        let pattern = self.pattern.clone();
+        let limit = self.max_body_size;
+
+        tokio::task::spawn_blocking(move || {
+            let mut entries = LimitedJsonArray::new(limit);
+
+            for entry in WalkDir::new(&root)
+                .min_depth(1)
+                .max_depth(max_depth)
+                .sort_by_file_name()
+            {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) if err.depth() == 0 => return Err(std::io::Error::from(err).into()),
+                    Err(err) => {
+                        skip(&root, &err);
+                        continue;
+                    }
+                };
+                let name = entry
+                    .path()
+                    .strip_prefix(&root)
+                    .unwrap_or_else(|_| entry.path())
+                    .to_string_lossy()
+                    .into_owned();
+
+                let matches = match &pattern {
+                    Some(pattern) => pattern.matches(&name),
+                    None => true,
+                };
+                if matches {
+                    match entry.metadata() {
+                        Ok(metadata) => entries.push(&describe(name, &metadata))?,
+                        Err(err) => skip(&root, &err),
+                    }
+                }
+            }
+
+            entries.into_bytes()
+        })
+        .await
+        .map_err(std::io::Error::from)?
+    }
+}
+
+// Entries removed while walking are skipped silently, others that can not be
+// read are logged so that they do not fail the whole listing.
+fn skip(root: &Path, err: &walkdir::Error) {
+    let removed = matches!(err.io_error(), Some(err) if err.kind() == std::io::ErrorKind::NotFound);
+    if !removed {
+        warn!("skipping entry of {}: {}", root.display(), err);
+    }
+}
+
+fn describe(name: String, metadata: &Metadata) -> serde_json::Value {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    };
+
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|mtime| mtime.as_secs_f64());
+
+    json!({
+        "name": name,
+        "size": metadata.len(),
+        "mtime": mtime,
+        "type": kind,
+        "permissions": permissions(metadata),
+    })
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    format!("{:04o}", metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &Metadata) -> String {
+    String::from(if metadata.permissions().readonly() {
+        "0444"
+    } else {
+        "0644"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::testing::TempDir;
+
+    fn dir(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("backup.tar", "0123456789");
+        dir.write("spool/a.msg", "a");
+        dir.write("spool/nested/b.msg", "bb");
+        dir
+    }
+
+    async fn fetch(config: Config) -> Vec<serde_json::Value> {
+        serde_json::from_slice(&config.fetch().await.unwrap()).unwrap()
+    }
+
+    fn field(entries: &[serde_json::Value], field: &str) -> Vec<serde_json::Value> {
+        entries.iter().map(|e| e[field].clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_walks_directory() {
+        let dir = dir("directory-walk");
+
+        let entries = fetch(Config::new(dir.root())).await;
+
+        assert_eq!(
+            field(&entries, "name"),
+            vec![
+                json!("backup.tar"),
+                json!("spool"),
+                json!("spool/a.msg"),
+                json!("spool/nested"),
+                json!("spool/nested/b.msg"),
+            ]
+        );
+        assert_eq!(
+            field(&entries, "type"),
+            vec![
+                json!("file"),
+                json!("dir"),
+                json!("file"),
+                json!("dir"),
+                json!("file")
+            ]
+        );
+        assert_eq!(entries[0]["size"], json!(10));
+        assert!(entries[0]["mtime"].as_f64().unwrap() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_max_depth_and_pattern() {
+        let dir = dir("directory-filter");
+
+        let entries = fetch(Config {
+            max_depth: Some(2),
+            pattern: Some(glob::Pattern::new("*.msg").unwrap()),
+            ..Config::new(dir.root())
+        })
+        .await;
+
+        assert_eq!(field(&entries, "name"), vec![json!("spool/a.msg")]);
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let dir = dir("directory-max-body-size");
+
+        let config = Config {
+            max_body_size: Some(100),
+            ..Config::new(dir.root())
+        };
+
+        assert!(matches!(
+            config.fetch().await,
+            Err(TargetError::BodyTooLarge(100))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_directory() {
+        let dir = dir("directory-missing");
+
+        let config = Config::new(dir.path("missing"));
+
+        assert!(matches!(config.fetch().await, Err(TargetError::IO(_))));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = dir("directory-permissions");
+        std::fs::set_permissions(
+            dir.path("backup.tar"),
+            std::fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+
+        let entries = fetch(Config {
+            max_depth: Some(1),
+            ..Config::new(dir.root())
+        })
+        .await;
+
+        assert_eq!(entries[0]["permissions"], json!("0640"));
+    }
+}
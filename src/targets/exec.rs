@@ -0,0 +1,241 @@
+use std::{process::Stdio, time::Duration};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, Command},
+    time::Instant,
+};
+
+use super::{limit::LimitedBuffer, TargetError};
+
+// how much of stderr to keep for the error of a failed command
+const STDERR_EXCERPT: u64 = 1024;
+
+#[derive(Debug)]
+pub struct Config {
+    pub command: String,
+    pub args: Vec<String>,
+    /// Environment variables passed on from the exporter, the command gets an
+    /// otherwise empty environment.
+    pub env: Vec<String>,
+    pub working_dir: Option<String>,
+    pub timeout: Option<Duration>,
+    pub max_body_size: Option<u64>,
+}
+
+impl Config {
+    pub fn new(command: String) -> Self {
+        Config {
+            command,
+            args: Vec::new(),
+            env: Vec::new(),
+            working_dir: None,
+            timeout: None,
+            max_body_size: None,
+        }
+    }
+
+    /// Runs the command and returns its stdout. The command runs in its own
+    /// process group, which is killed if it times out, runs past `deadline`
+    /// or produces too much output.
+    pub async fn fetch(&self, deadline: Option<Instant>) -> Result<Bytes, TargetError> {
+        let mut command = std::process::Command::new(&self.command);
+        command
+            .args(&self.args)
+            .env_clear()
+            .envs(
+                self.env
+                    .iter()
+                    .filter_map(|key| std::env::var_os(key).map(|value| (key, value))),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let started = Instant::now();
+        let mut child = Command::from(command).kill_on_drop(true).spawn()?;
+
+        let until = self
+            .timeout
+            .map(|timeout| started + timeout)
+            .into_iter()
+            .chain(deadline)
+            .min();
+        let result = match until {
+            Some(until) => tokio::time::timeout_at(until, self.run(&mut child))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(TargetError::Timeout(
+                        until.saturating_duration_since(started),
+                    ))
+                }),
+            None => self.run(&mut child).await,
+        };
+
+        if result.is_err() {
+            kill(&mut child).await;
+        }
+
+        result
+    }
+
+    async fn run(&self, child: &mut Child) -> Result<Bytes, TargetError> {
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        let (stdout, stderr) = tokio::try_join!(
+            LimitedBuffer::new(self.max_body_size).read_from(stdout),
+            excerpt(stderr),
+        )?;
+
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(TargetError::Exit(status, stderr));
+        }
+
+        Ok(stdout)
+    }
+}
+
+// Reads the start of `reader` and discards the rest, so the command never
+// blocks on a full stderr pipe.
+async fn excerpt<R>(mut reader: R) -> Result<String, TargetError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::new();
+    (&mut reader)
+        .take(STDERR_EXCERPT)
+        .read_to_end(&mut buffer)
+        .await?;
+    tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+
+    Ok(String::from_utf8_lossy(&buffer).trim().to_owned())
+}
+
+async fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // the process group id is the pid of the command, see `process_group(0)`
+        let _ = nix::sys::signal::killpg(
+            nix::unistd::Pid::from_raw(pid as i32),
+            nix::sys::signal::Signal::SIGKILL,
+        );
+    }
+
+    let _ = child.kill().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::testing::{wait_until, TempDir};
+
+    fn sh(script: &str) -> Config {
+        Config {
+            args: vec!["-c".to_owned(), script.to_owned()],
+            ..Config::new("/bin/sh".to_owned())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stdout() {
+        let config = sh(r#"echo '{"val": 1}'"#);
+
+        assert_eq!(config.fetch(None).await.unwrap(), "{\"val\": 1}\n");
+    }
+
+    #[tokio::test]
+    async fn test_env_allow_list() {
+        std::env::set_var("DATA_EXPORTER_TEST_ALLOWED", "allowed");
+        std::env::set_var("DATA_EXPORTER_TEST_DENIED", "denied");
+
+        let config = Config {
+            env: vec!["DATA_EXPORTER_TEST_ALLOWED".to_owned()],
+            ..sh("echo $DATA_EXPORTER_TEST_ALLOWED $DATA_EXPORTER_TEST_DENIED")
+        };
+
+        assert_eq!(config.fetch(None).await.unwrap(), "allowed\n");
+    }
+
+    #[tokio::test]
+    async fn test_working_dir() {
+        let config = Config {
+            working_dir: Some("/".to_owned()),
+            ..sh("pwd")
+        };
+
+        assert_eq!(config.fetch(None).await.unwrap(), "/\n");
+    }
+
+    #[tokio::test]
+    async fn test_non_zero_exit() {
+        let config = sh("echo partial; echo 'something broke' >&2; exit 3");
+
+        match config.fetch(None).await {
+            Err(TargetError::Exit(status, stderr)) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "something broke");
+            }
+            other => panic!("expected exit error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_process_group() {
+        let dir = TempDir::new("exec-process-group");
+        let pid_file = dir.path("pid");
+        let config = Config {
+            timeout: Some(Duration::from_millis(200)),
+            ..sh(&format!(
+                "sleep 10 & echo $! > {}; sleep 10; echo done",
+                pid_file
+            ))
+        };
+
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::Timeout(..))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the background process must be killed as well, not just the shell
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let pid = nix::unistd::Pid::from_raw(pid.trim().parse().unwrap());
+        wait_until(|| nix::sys::signal::kill(pid, None) == Err(nix::errno::Errno::ESRCH)).await;
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let config = sh("sleep 10");
+
+        let deadline = Instant::now() + Duration::from_millis(200);
+        assert!(matches!(
+            config.fetch(Some(deadline)).await,
+            Err(TargetError::Timeout(..))
+        ));
+        assert!(Instant::now() < deadline + Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let config = Config {
+            max_body_size: Some(10),
+            ..sh("while true; do echo 0123456789; done")
+        };
+
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::BodyTooLarge(10))
+        ));
+    }
+}
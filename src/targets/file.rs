@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use regex::Regex;
+
+use super::{compression::Compression, limit::LimitedBuffer, Fetched, TargetError};
+
+#[derive(Debug)]
+pub struct Config {
+    /// Path of a single file, a directory whose files are all read, or a glob
+    /// pattern that is expanded on every fetch.
+    pub path: String,
+    /// Regex matched against the path of each file, named captures are added
+    /// as labels. Files whose path does not match are skipped.
+    pub path_regex: Option<Regex>,
+    /// Limit for the size of each file, after decompressing it.
+    pub max_body_size: Option<u64>,
+    pub compression: Compression,
+}
+
+impl Config {
+    pub fn new(path: String) -> Self {
+        Config {
+            path,
+            path_regex: None,
+            max_body_size: None,
+            compression: Compression::Auto,
+        }
+    }
+
+    pub fn is_pattern(path: &str) -> bool {
+        path.contains(['*', '?', '['])
+    }
+
+    pub async fn fetch(&self) -> Result<Vec<Fetched>, TargetError> {
+        let expanded =
+            Self::is_pattern(&self.path) || tokio::fs::metadata(&self.path).await?.is_dir();
+        let paths = if expanded {
+            self.expand().await?
+        } else {
+            vec![self.path.clone()]
+        };
+
+        let mut fetched = Vec::new();
+        for path in paths {
+            let labels = match &self.path_regex {
+                Some(regex) => match path_labels(regex, &path) {
+                    Some(labels) => labels,
+                    None => continue,
+                },
+                None => Vec::new(),
+            };
+
+            let data = match self.read(&path).await {
+                // removed after the pattern or directory was expanded
+                Err(TargetError::IO(err))
+                    if expanded && err.kind() == std::io::ErrorKind::NotFound =>
+                {
+                    continue
+                }
+                result => result?,
+            };
+
+            fetched.push(Fetched {
+                target: path,
+                labels,
+                data,
+                unchanged: false,
+            });
+        }
+
+        Ok(fetched)
+    }
+
+    async fn read(&self, path: &str) -> Result<Bytes, TargetError> {
+        let file = tokio::fs::File::open(path).await?;
+        let buffer = LimitedBuffer::new(self.max_body_size);
+        buffer.check_size(file.metadata().await?.len())?;
+
+        self.compression
+            .decompress(buffer.read_from(file).await?, self.max_body_size)
+            .await
+    }
+
+    // Lists the regular files matching the pattern, or directly inside the
+    // directory, in alphabetical order.
+    async fn expand(&self) -> Result<Vec<String>, TargetError> {
+        let path = self.path.clone();
+
+        let paths = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<PathBuf>> {
+            let paths = if Self::is_pattern(&path) {
+                let pattern = glob::glob(&path)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                pattern
+                    .map(|entry| entry.map_err(std::io::Error::from))
+                    .collect::<std::io::Result<Vec<_>>>()?
+            } else {
+                let mut paths = std::fs::read_dir(&path)?
+                    .map(|entry| entry.map(|e| e.path()))
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                paths.sort();
+                paths
+            };
+
+            // `is_file` reads the metadata of each path, so it blocks as well
+            Ok(paths.into_iter().filter(|path| path.is_file()).collect())
+        })
+        .await
+        .map_err(std::io::Error::from)??;
+
+        Ok(paths
+            .into_iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect())
+    }
+}
+
+fn path_labels(regex: &Regex, path: &str) -> Option<Vec<(String, String)>> {
+    let captures = regex.captures(path)?;
+
+    Some(
+        regex
+            .capture_names()
+            .flatten()
+            .map(|name| {
+                let value = captures.name(name).map_or("", |m| m.as_str());
+                (name.to_owned(), value.to_owned())
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::testing::TempDir;
+
+    fn dir(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new(name);
+        std::fs::create_dir(dir.path("sub")).unwrap();
+        for (file, content) in files {
+            dir.write(file, content);
+        }
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_single_file() {
+        let dir = dir("file-single", &[("status.json", "{}")]);
+
+        let fetched = Config::new(dir.path("status.json")).fetch().await.unwrap();
+
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].target, dir.path("status.json"));
+        assert_eq!(fetched[0].data, "{}");
+    }
+
+    #[tokio::test]
+    async fn test_missing_file() {
+        let config = Config::new("does/not/exist.json".to_owned());
+
+        assert!(matches!(config.fetch().await, Err(TargetError::IO(..))));
+    }
+
+    #[tokio::test]
+    async fn test_glob_with_path_labels() {
+        let dir = dir(
+            "file-glob",
+            &[("a.json", "1"), ("b.json", "2"), ("c.txt", "3")],
+        );
+
+        let config = Config {
+            path_regex: Some(Regex::new(r"/(?P<job>[^/]+)\.json$").unwrap()),
+            ..Config::new(dir.path("*.json"))
+        };
+        let fetched = config.fetch().await.unwrap();
+
+        assert_eq!(
+            fetched
+                .iter()
+                .map(|f| (f.labels.clone(), f.data.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (vec![("job".to_owned(), "a".to_owned())], Bytes::from("1")),
+                (vec![("job".to_owned(), "b".to_owned())], Bytes::from("2")),
+            ]
+        );
+
+        std::fs::write(dir.path("d.json"), "4").unwrap();
+        assert_eq!(config.fetch().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_directory() {
+        let dir = dir("file-dir", &[("b", "2"), ("a", "1")]);
+
+        let fetched = Config::new(dir.root()).fetch().await.unwrap();
+
+        assert_eq!(
+            fetched.iter().map(|f| f.target.clone()).collect::<Vec<_>>(),
+            vec![dir.path("a"), dir.path("b")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_regex_skips_unmatched_files() {
+        let dir = dir("file-skip", &[("job-1.json", "1"), ("other.json", "2")]);
+
+        let config = Config {
+            path_regex: Some(Regex::new(r"job-(?P<id>\d+)").unwrap()),
+            ..Config::new(dir.path("*.json"))
+        };
+        let fetched = config.fetch().await.unwrap();
+
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].labels, vec![("id".to_owned(), "1".to_owned())]);
+    }
+}
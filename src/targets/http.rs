@@ -1,20 +1,657 @@
+use std::{sync::Mutex, time::Duration};
+
 use bytes::Bytes;
+use metrics::gauge;
+use tokio::time::Instant;
+use tracing::warn;
+
+use super::{
+    compression::Compression,
+    limit::LimitedBuffer,
+    pagination::{self, Pagination},
+    retry::RetryPolicy,
+    unix::UnixSocket,
+    TargetError,
+};
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-const NAME: &str = env!("CARGO_PKG_NAME");
+/// `User-Agent` of all requests, e.g. `data-exporter/0.1.0`.
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Value that is either given inline or read from a file on every fetch, so
+/// that rotated secrets are picked up without a restart.
+#[derive(Debug)]
+pub enum Source {
+    Content(String),
+    File(String),
+}
+
+impl Source {
+    async fn read(&self) -> Result<Bytes, TargetError> {
+        match self {
+            Self::Content(content) => Ok(Bytes::from(content.clone())),
+            Self::File(path) => Ok(Bytes::from(tokio::fs::read(path).await?)),
+        }
+    }
+
+    async fn read_secret(&self) -> Result<String, TargetError> {
+        match self {
+            Self::Content(content) => Ok(content.clone()),
+            Self::File(path) => Ok(tokio::fs::read_to_string(path).await?.trim().to_owned()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: Option<Source>,
+    },
+    Bearer(Source),
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    etag: Option<reqwest::header::HeaderValue>,
+    last_modified: Option<reqwest::header::HeaderValue>,
+    body: Bytes,
+}
+
+/// Last response of a target, used to make conditional requests with
+/// `If-None-Match` and `If-Modified-Since`, and to serve the body again when
+/// the server responds with `304 Not Modified`.
+#[derive(Debug, Default)]
+pub struct ResponseCache(Mutex<Option<CachedResponse>>);
+
+impl ResponseCache {
+    fn validators(&self) -> Vec<(reqwest::header::HeaderName, reqwest::header::HeaderValue)> {
+        let cached = self.0.lock().unwrap();
+        let mut validators = Vec::new();
+
+        if let Some(cached) = cached.as_ref() {
+            if let Some(etag) = &cached.etag {
+                validators.push((reqwest::header::IF_NONE_MATCH, etag.clone()));
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                validators.push((reqwest::header::IF_MODIFIED_SINCE, last_modified.clone()));
+            }
+        }
+
+        validators
+    }
+
+    fn body(&self) -> Option<Bytes> {
+        self.0.lock().unwrap().as_ref().map(|c| c.body.clone())
+    }
+
+    fn store(&self, headers: &reqwest::header::HeaderMap, body: Bytes) {
+        let etag = headers.get(reqwest::header::ETAG).cloned();
+        let last_modified = headers.get(reqwest::header::LAST_MODIFIED).cloned();
+
+        *self.0.lock().unwrap() =
+            (etag.is_some() || last_modified.is_some()).then(|| CachedResponse {
+                etag,
+                last_modified,
+                body,
+            });
+    }
+}
+
+/// Proxy that the requests of a target are sent through.
+#[derive(Debug, Default)]
+pub enum Proxy {
+    /// The proxies configured by the `HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `NO_PROXY` environment variables.
+    #[default]
+    Environment,
+    /// Send all requests directly, ignoring the environment variables.
+    Disabled,
+    Url(reqwest::Proxy),
+}
+
+/// Settings that apply to the connection rather than to a single request.
+/// Each target gets its own long-lived client built from these, so
+/// connections are pooled between scrapes.
+#[derive(Debug, Default)]
+pub struct ClientConfig {
+    pub connect_timeout: Option<Duration>,
+    pub tls: Option<rustls::ClientConfig>,
+    pub proxy: Proxy,
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl ClientConfig {
+    pub fn build(&self) -> reqwest::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().user_agent(USER_AGENT);
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(tls) = &self.tls {
+            builder = builder.use_preconfigured_tls(tls.clone());
+        }
+
+        match &self.proxy {
+            Proxy::Environment => {}
+            Proxy::Disabled => builder = builder.no_proxy(),
+            Proxy::Url(proxy) => builder = builder.proxy(proxy.clone()),
+        }
+
+        if let Some(keepalive) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(keepalive);
+        }
+
+        builder.build()
+    }
+}
+
+/// Body of a response, or of all pages of a paginated response.
+#[derive(Debug)]
+pub struct Body {
+    pub data: Bytes,
+    /// Whether the server responded with `304 Not Modified` and the data was
+    /// served from the response cache.
+    pub unchanged: bool,
+}
 
 #[derive(Debug)]
 pub struct Config {
     pub url: String,
+    pub method: reqwest::Method,
+    pub headers: reqwest::header::HeaderMap,
+    pub body: Option<Source>,
+    pub auth: Option<Auth>,
+    pub timeout: Option<Duration>,
+    /// Status codes that are accepted as a successful response, any 2xx
+    /// status is accepted if empty.
+    pub valid_status_codes: Vec<u16>,
+    pub retry: Option<RetryPolicy>,
+    pub cache: Option<ResponseCache>,
+    pub pagination: Option<Pagination>,
+    /// Limit for the size of the response body, after decompressing it.
+    pub max_body_size: Option<u64>,
+    pub compression: Compression,
+    pub unix_socket: Option<UnixSocket>,
+    pub client: reqwest::Client,
 }
 
 impl Config {
-    pub async fn fetch(&self) -> reqwest::Result<Bytes> {
-        let client = reqwest::Client::new();
-        let req = client
-            .request(reqwest::Method::GET, &self.url)
-            .header(reqwest::header::USER_AGENT, format!("{}/{}", NAME, VERSION));
+    pub fn new(url: String, client: reqwest::Client) -> Self {
+        Config {
+            url,
+            method: reqwest::Method::GET,
+            headers: reqwest::header::HeaderMap::new(),
+            body: None,
+            auth: None,
+            timeout: None,
+            valid_status_codes: Vec::new(),
+            retry: None,
+            cache: None,
+            pagination: None,
+            max_body_size: None,
+            compression: Compression::Auto,
+            unix_socket: None,
+            client,
+        }
+    }
+
+    pub async fn fetch(&self, deadline: Option<Instant>) -> Result<Body, TargetError> {
+        let pagination = match &self.pagination {
+            Some(pagination) => pagination,
+            None => return Ok(self.fetch_page(&self.url, deadline).await?.1),
+        };
+
+        // the limit applies to all pages together
+        let limit = LimitedBuffer::new(self.max_body_size);
+        let mut size = 0;
+        let mut pages = Vec::new();
+        let mut unchanged = true;
+        let mut url = self.url.clone();
+
+        loop {
+            let (headers, body) = self.fetch_page(&url, deadline).await?;
+            size += body.data.len() as u64;
+            limit.check_size(size)?;
+
+            let next = pagination.next_url(&url, &headers, &body.data)?;
+            unchanged &= body.unchanged;
+            pages.push(body.data);
+
+            match next {
+                Some(next) if pages.len() < pagination.max_pages as usize => url = next,
+                Some(_) => {
+                    warn!(
+                        "stopped paginating {} after reaching max_pages {}",
+                        self.url, pagination.max_pages
+                    );
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let data = pagination::merge(pages)?;
+        limit.check_size(data.len() as u64)?;
+
+        Ok(Body { data, unchanged })
+    }
+
+    async fn fetch_page(
+        &self,
+        url: &str,
+        deadline: Option<Instant>,
+    ) -> Result<(reqwest::header::HeaderMap, Body), TargetError> {
+        match &self.retry {
+            Some(retry) => retry.run(deadline, || self.fetch_once(url)).await,
+            None => self.fetch_once(url).await,
+        }
+    }
+
+    async fn fetch_once(
+        &self,
+        url: &str,
+    ) -> Result<(reqwest::header::HeaderMap, Body), TargetError> {
+        // reqwest applies the timeout to the whole request including the
+        // body, for unix sockets it has to be applied here
+        match (&self.unix_socket, self.timeout) {
+            (Some(_), Some(timeout)) => tokio::time::timeout(timeout, self.exchange(url))
+                .await
+                .unwrap_or(Err(TargetError::Timeout(timeout))),
+            _ => self.exchange(url).await,
+        }
+    }
+
+    async fn exchange(&self, url: &str) -> Result<(reqwest::header::HeaderMap, Body), TargetError> {
+        let mut req = self.client.request(self.method.clone(), url);
+
+        if let Some(timeout) = self.timeout {
+            req = req.timeout(timeout);
+        }
+
+        req = req.headers(self.headers.clone());
+
+        if let Some(body) = &self.body {
+            req = req.body(body.read().await?);
+        }
+
+        match &self.auth {
+            Some(Auth::Basic { username, password }) => {
+                let password = match password {
+                    Some(password) => Some(password.read_secret().await?),
+                    None => None,
+                };
+                req = req.basic_auth(username, password);
+            }
+            Some(Auth::Bearer(token)) => {
+                req = req.bearer_auth(token.read_secret().await?);
+            }
+            None => {}
+        }
+
+        if let Some(cache) = &self.cache {
+            for (name, value) in cache.validators() {
+                req = req.header(name, value);
+            }
+        }
+
+        let mut resp = match &self.unix_socket {
+            Some(socket) => socket.execute(req.build()?).await?,
+            None => req.send().await?,
+        };
+
+        let status = resp.status();
+        gauge!(
+            crate::TARGET_HTTP_STATUS_CODE,
+            f64::from(status.as_u16()),
+            "target" => self.url.clone()
+        );
+
+        let headers = resp.headers().clone();
+
+        if status == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(data) = self.cache.as_ref().and_then(ResponseCache::body) {
+                let body = Body {
+                    data,
+                    unchanged: true,
+                };
+                return Ok((headers, body));
+            }
+        }
+
+        if !self.is_valid_status(status) {
+            return Err(TargetError::Status(status));
+        }
+
+        let mut body = LimitedBuffer::new(self.max_body_size);
+        if let Some(length) = resp.content_length() {
+            body.check_size(length)?;
+        }
+        while let Some(chunk) = resp.chunk().await? {
+            body.extend(&chunk)?;
+        }
+        let body = self
+            .compression
+            .decompress(body.into_bytes(), self.max_body_size)
+            .await?;
+
+        if let Some(cache) = &self.cache {
+            cache.store(&headers, body.clone());
+        }
+
+        let body = Body {
+            data: body,
+            unchanged: false,
+        };
+        Ok((headers, body))
+    }
+
+    fn is_valid_status(&self, status: reqwest::StatusCode) -> bool {
+        if self.valid_status_codes.is_empty() {
+            status.is_success()
+        } else {
+            self.valid_status_codes.contains(&status.as_u16())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::HeaderMap, routing::any, Router};
+
+    use super::*;
+    use crate::targets::testing::{serve, TempDir};
+
+    fn config(url: String) -> Config {
+        Config::new(url, ClientConfig::default().build().unwrap())
+    }
+
+    async fn authorization(headers: HeaderMap) -> String {
+        headers
+            .get("authorization")
+            .map(|v| v.to_str().unwrap().to_owned())
+            .unwrap_or_default()
+    }
+
+    async fn echo(method: axum::http::Method, headers: HeaderMap, body: String) -> String {
+        let header = headers
+            .get("x-api-key")
+            .map(|v| v.to_str().unwrap().to_owned())
+            .unwrap_or_default();
+        format!("{} {} {}", method, header, body)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_defaults_to_get() {
+        let addr = serve(Router::new().route("/", any(echo))).await;
+
+        let config = config(format!("http://{}/", addr));
+        let resp = config.fetch(None).await.unwrap().data;
+
+        assert_eq!(resp, "GET  ");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_sends_method_headers_and_body() {
+        let addr = serve(Router::new().route("/", any(echo))).await;
+
+        let config = Config {
+            method: reqwest::Method::POST,
+            headers: reqwest::header::HeaderMap::from_iter([(
+                reqwest::header::HeaderName::from_static("x-api-key"),
+                reqwest::header::HeaderValue::from_static("secret"),
+            )]),
+            body: Some(Source::Content(r#"{"query": "all"}"#.to_owned())),
+            ..config(format!("http://{}/", addr))
+        };
+        let resp = config.fetch(None).await.unwrap().data;
+
+        assert_eq!(resp, r#"POST secret {"query": "all"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_body_from_missing_file() {
+        let config = Config {
+            method: reqwest::Method::POST,
+            body: Some(Source::File("does/not/exist.json".to_owned())),
+            ..config("http://127.0.0.1:1/".to_owned())
+        };
+
+        assert!(matches!(config.fetch(None).await, Err(TargetError::IO(..))));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_basic_auth() {
+        let addr = serve(Router::new().route("/", any(authorization))).await;
+
+        let config = Config {
+            auth: Some(Auth::Basic {
+                username: "user".to_owned(),
+                password: Some(Source::Content("pass".to_owned())),
+            }),
+            ..config(format!("http://{}/", addr))
+        };
+
+        assert_eq!(config.fetch(None).await.unwrap().data, "Basic dXNlcjpwYXNz");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_bearer_token_file_is_reread() {
+        let addr = serve(Router::new().route("/", any(authorization))).await;
+
+        let dir = TempDir::new("http-token");
+        dir.write("token", "first\n");
+
+        let config = Config {
+            auth: Some(Auth::Bearer(Source::File(dir.path("token")))),
+            ..config(format!("http://{}/", addr))
+        };
+
+        assert_eq!(config.fetch(None).await.unwrap().data, "Bearer first");
+
+        dir.write("token", "second\n");
+        assert_eq!(config.fetch(None).await.unwrap().data, "Bearer second");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timeout() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "too late"
+        };
+        let addr = serve(Router::new().route("/", any(slow))).await;
+
+        let config = Config {
+            timeout: Some(Duration::from_millis(100)),
+            ..config(format!("http://{}/", addr))
+        };
+
+        match config.fetch(None).await {
+            Err(TargetError::HTTP(err)) => assert!(err.is_timeout()),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_error_status() {
+        let fail = || async { (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "error page") };
+        let addr = serve(Router::new().route("/", any(fail))).await;
+
+        let config = config(format!("http://{}/", addr));
+
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::Status(
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_valid_status_codes() {
+        let not_found = || async { (axum::http::StatusCode::NOT_FOUND, "[]") };
+        let addr = serve(Router::new().route("/", any(not_found))).await;
+
+        let config = Config {
+            valid_status_codes: vec![200, 404],
+            ..config(format!("http://{}/", addr))
+        };
+
+        assert_eq!(config.fetch(None).await.unwrap().data, "[]");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_retries() {
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let flaky = {
+            let attempts = attempts.clone();
+            move || async move {
+                match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                    0 => (axum::http::StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+                    _ => (axum::http::StatusCode::OK, "OK"),
+                }
+            }
+        };
+        let addr = serve(Router::new().route("/", any(flaky))).await;
+
+        let config = Config {
+            retry: Some(RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            }),
+            ..config(format!("http://{}/", addr))
+        };
+
+        assert_eq!(config.fetch(None).await.unwrap().data, "OK");
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_not_modified() {
+        let not_modified = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let etag = {
+            let not_modified = not_modified.clone();
+            move |headers: HeaderMap| async move {
+                if headers.get("if-none-match").and_then(|v| v.to_str().ok()) == Some("\"v1\"") {
+                    not_modified.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    (
+                        axum::http::StatusCode::NOT_MODIFIED,
+                        [("etag", "\"v1\"")],
+                        "",
+                    )
+                } else {
+                    (axum::http::StatusCode::OK, [("etag", "\"v1\"")], "body")
+                }
+            }
+        };
+        let addr = serve(Router::new().route("/", any(etag))).await;
+
+        let config = Config {
+            cache: Some(ResponseCache::default()),
+            ..config(format!("http://{}/", addr))
+        };
+
+        let body = config.fetch(None).await.unwrap();
+        assert_eq!(body.data, "body");
+        assert!(!body.unchanged);
+
+        let body = config.fetch(None).await.unwrap();
+        assert_eq!(body.data, "body");
+        assert!(body.unchanged);
+        assert_eq!(not_modified.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_link_header_pagination() {
+        let pages = |axum::extract::RawQuery(query): axum::extract::RawQuery| async move {
+            match query.as_deref() {
+                Some("page=2") => (
+                    [("link", r#"</?page=1>; rel="prev", </?page=3>; rel="next""#)],
+                    r#"[{"page": 2}]"#,
+                ),
+                Some("page=3") => ([("link", r#"</?page=2>; rel="prev""#)], r#"[{"page": 3}]"#),
+                _ => ([("link", r#"</?page=2>; rel="next""#)], r#"[{"page": 1}]"#),
+            }
+        };
+        let addr = serve(Router::new().route("/", any(pages))).await;
+
+        let config = Config {
+            pagination: Some(Pagination {
+                next: pagination::NextPage::LinkHeader,
+                max_pages: 10,
+            }),
+            ..config(format!("http://{}/", addr))
+        };
+        assert_eq!(
+            config.fetch(None).await.unwrap().data,
+            r#"[{"page":1},{"page":2},{"page":3}]"#
+        );
+
+        let config = Config {
+            pagination: Some(Pagination {
+                next: pagination::NextPage::LinkHeader,
+                max_pages: 2,
+            }),
+            ..config
+        };
+        assert_eq!(
+            config.fetch(None).await.unwrap().data,
+            r#"[{"page":1},{"page":2}]"#
+        );
+
+        // every page fits, but not all of them together
+        let config = Config {
+            max_body_size: Some(20),
+            ..config
+        };
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::BodyTooLarge(20))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_through_proxy() {
+        let proxy = |uri: axum::http::Uri, headers: HeaderMap| async move {
+            let auth = headers
+                .get("proxy-authorization")
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default();
+            format!("{} {}", uri, auth)
+        };
+        let addr = serve(Router::new().fallback(proxy)).await;
+
+        let client = ClientConfig {
+            proxy: Proxy::Url(
+                reqwest::Proxy::all(format!("http://{}", addr))
+                    .unwrap()
+                    .basic_auth("user", "pass"),
+            ),
+            ..ClientConfig::default()
+        }
+        .build()
+        .unwrap();
+        let config = Config::new("http://upstream.invalid/data".to_owned(), client);
+
+        assert_eq!(
+            config.fetch(None).await.unwrap().data,
+            "http://upstream.invalid/data Basic dXNlcjpwYXNz"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_max_body_size() {
+        let large = || async { "0123456789" };
+        let addr = serve(Router::new().route("/", any(large))).await;
+
+        let config = Config {
+            max_body_size: Some(5),
+            ..config(format!("http://{}/", addr))
+        };
 
-        req.send().await?.bytes().await
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::BodyTooLarge(5))
+        ));
     }
 }
@@ -0,0 +1,128 @@
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::TargetError;
+
+/// Buffer for data read from a target, that fails as soon as more than
+/// `limit` bytes are added to it, so that oversized payloads are never held
+/// in memory in full.
+pub struct LimitedBuffer {
+    buffer: BytesMut,
+    limit: Option<u64>,
+}
+
+impl LimitedBuffer {
+    pub fn new(limit: Option<u64>) -> Self {
+        LimitedBuffer {
+            buffer: BytesMut::new(),
+            limit,
+        }
+    }
+
+    /// Fails early if a known size of the data, e.g. from `Content-Length`,
+    /// already exceeds the limit.
+    pub fn check_size(&self, size: u64) -> Result<(), TargetError> {
+        match self.limit {
+            Some(limit) if size > limit => Err(TargetError::BodyTooLarge(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn extend(&mut self, chunk: &[u8]) -> Result<(), TargetError> {
+        self.check_size((self.buffer.len() + chunk.len()) as u64)?;
+        self.buffer.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    pub async fn read_from<R>(mut self, mut reader: R) -> Result<Bytes, TargetError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut chunk).await? {
+                0 => return Ok(self.into_bytes()),
+                n => self.extend(&chunk[..n])?,
+            }
+        }
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.buffer.freeze()
+    }
+}
+
+/// JSON array built from values added one by one, e.g. the rows of a query,
+/// that fails as soon as it grows over `limit` bytes.
+pub struct LimitedJsonArray {
+    buffer: LimitedBuffer,
+    empty: bool,
+}
+
+impl LimitedJsonArray {
+    pub fn new(limit: Option<u64>) -> Self {
+        LimitedJsonArray {
+            buffer: LimitedBuffer::new(limit),
+            empty: true,
+        }
+    }
+
+    pub fn push(&mut self, value: &serde_json::Value) -> Result<(), TargetError> {
+        self.buffer.extend(if self.empty { b"[" } else { b"," })?;
+        self.buffer
+            .extend(&serde_json::to_vec(value).expect("value is a valid json value"))?;
+        self.empty = false;
+        Ok(())
+    }
+
+    pub fn into_bytes(mut self) -> Result<Bytes, TargetError> {
+        if self.empty {
+            self.buffer.extend(b"[")?;
+        }
+        self.buffer.extend(b"]")?;
+        Ok(self.buffer.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_within_limit() {
+        let data = b"0123456789".as_slice();
+
+        let read = LimitedBuffer::new(Some(10)).read_from(data).await.unwrap();
+        assert_eq!(read, "0123456789");
+
+        let read = LimitedBuffer::new(None).read_from(data).await.unwrap();
+        assert_eq!(read, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_read_exceeding_limit() {
+        let data = b"0123456789".as_slice();
+
+        assert!(matches!(
+            LimitedBuffer::new(Some(9)).read_from(data).await,
+            Err(TargetError::BodyTooLarge(9))
+        ));
+    }
+
+    #[test]
+    fn test_json_array() {
+        let mut array = LimitedJsonArray::new(Some(8));
+        array.push(&serde_json::json!(1)).unwrap();
+        array.push(&serde_json::json!("a")).unwrap();
+        assert_eq!(array.into_bytes().unwrap(), r#"[1,"a"]"#);
+
+        assert_eq!(LimitedJsonArray::new(None).into_bytes().unwrap(), "[]");
+
+        let mut array = LimitedJsonArray::new(Some(8));
+        array.push(&serde_json::json!("abc")).unwrap();
+        assert!(matches!(
+            array.push(&serde_json::json!("def")),
+            Err(TargetError::BodyTooLarge(8))
+        ));
+    }
+}
@@ -1,11 +1,50 @@
+use std::{process::ExitStatus, sync::Arc, time::Duration};
+
 use bytes::Bytes;
-use tokio::io::AsyncReadExt;
+use tokio::time::Instant;
+
+pub mod compression;
+pub mod convert;
+pub mod directory;
+pub mod exec;
+pub mod file;
 pub mod http;
+pub mod limit;
+pub mod mqtt;
+pub mod pagination;
+pub mod push;
+pub mod redis;
+pub mod retry;
+pub mod s3;
+pub mod socket;
+pub mod sql;
+pub mod sqlite;
+pub mod stream;
+pub mod tail;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod tls;
+pub mod unix;
 
 #[derive(Debug)]
 pub enum TargetError {
     HTTP(reqwest::Error),
+    Socket(hyper::Error),
     IO(std::io::Error),
+    Status(reqwest::StatusCode),
+    Pagination(String),
+    BodyTooLarge(u64),
+    Timeout(Duration),
+    Exit(ExitStatus, String),
+    Sqlite(rusqlite::Error),
+    Redis(::redis::RedisError),
+    Sql(sqlx::Error),
+    /// Invalid object listing or credentials of an s3 target.
+    S3(String),
+    /// Nothing was pushed to a push target or received by a stream or mqtt
+    /// target yet, or the data expired. Also returned by an s3 target when
+    /// there is no object under its prefix.
+    NoData,
 }
 impl From<std::io::Error> for TargetError {
     fn from(e: std::io::Error) -> Self {
@@ -17,30 +56,172 @@ impl From<reqwest::Error> for TargetError {
         TargetError::HTTP(e)
     }
 }
+impl From<hyper::Error> for TargetError {
+    fn from(e: hyper::Error) -> Self {
+        TargetError::Socket(e)
+    }
+}
+impl From<rusqlite::Error> for TargetError {
+    fn from(e: rusqlite::Error) -> Self {
+        TargetError::Sqlite(e)
+    }
+}
+impl From<sqlx::Error> for TargetError {
+    fn from(e: sqlx::Error) -> Self {
+        TargetError::Sql(e)
+    }
+}
+impl From<::redis::RedisError> for TargetError {
+    fn from(e: ::redis::RedisError) -> Self {
+        TargetError::Redis(e)
+    }
+}
+
+/// Data read from a target, or from one of the files matched by a file
+/// target.
+#[derive(Debug)]
+pub struct Fetched {
+    /// Value of the `target` label.
+    pub target: String,
+    /// Additional labels, e.g. captured from the path of a file.
+    pub labels: Vec<(String, String)>,
+    pub data: Bytes,
+    /// Whether the data is known to be the same as on the previous fetch,
+    /// e.g. because an http server responded with `304 Not Modified`.
+    pub unchanged: bool,
+}
 
 #[derive(Debug)]
 pub enum Target {
-    Http(http::Config),
-    File { path: String },
+    Http(Box<http::Config>),
+    Exec(Box<exec::Config>),
+    Tcp(Box<socket::Config>),
+    Udp(Box<socket::Config>),
+    Sqlite(Box<sqlite::Config>),
+    File(Box<file::Config>),
+    Push(Box<push::Config>),
+    Redis(Box<redis::Config>),
+    /// Data given inline in the config.
+    Static(Bytes),
+    Directory(Box<directory::Config>),
+    Tail(Box<tail::Config>),
+    /// Shared with the background task that reads the stream.
+    Stream(Arc<stream::Config>),
+    Mqtt(Arc<mqtt::Config>),
+    S3(Box<s3::Config>),
+    /// A postgres or mysql database.
+    Sql(Box<sql::Config>),
 }
 
 impl Target {
     pub fn describe(&self) -> &str {
         match self {
-            Self::Http(http::Config { url }) => url,
-            Self::File { path } => path,
+            Self::Http(config) => &config.url,
+            Self::Exec(config) => &config.command,
+            Self::Tcp(config) | Self::Udp(config) => &config.address,
+            Self::Sqlite(config) => &config.path,
+            Self::File(config) => &config.path,
+            Self::Push(config) => &config.name,
+            Self::Redis(config) => &config.address,
+            Self::Static(_) => "static",
+            Self::Directory(config) => &config.path,
+            Self::Tail(config) => &config.path,
+            Self::Stream(config) => &config.url,
+            Self::Mqtt(config) => &config.url,
+            Self::S3(config) => &config.location,
+            Self::Sql(config) => &config.dsn,
         }
     }
-    pub async fn fetch(&self) -> Result<Bytes, TargetError> {
-        match &self {
-            Self::Http(config) => Ok(config.fetch().await?),
-            Self::File { path } => {
-                let mut file = tokio::fs::File::open(path).await?;
-                let mut buffer = Vec::new();
-                file.read_to_end(&mut buffer).await?;
+    /// Whether fetched data can be marked as `unchanged`, which makes it
+    /// worth keeping the result of parsing it.
+    pub fn reports_unchanged(&self) -> bool {
+        matches!(self, Self::Http(config) if config.cache.is_some())
+    }
 
-                Ok(Bytes::from(buffer))
+    /// Called once the fetched data was collected, so that a tail target only
+    /// moves past lines that were used.
+    pub async fn commit(&self) -> Result<(), TargetError> {
+        match self {
+            Self::Tail(config) => config.commit().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Fetches the data of the target, and fails with `Timeout` if that is
+    /// not done by `deadline`. Http targets retry within the deadline, and
+    /// exec targets kill their command at it, so they handle it themselves.
+    pub async fn fetch(&self, deadline: Option<Instant>) -> Result<Vec<Fetched>, TargetError> {
+        match (self, deadline) {
+            (Self::Http(_) | Self::Exec(_), _) | (_, None) => self.fetch_data(deadline).await,
+            (_, Some(deadline)) => {
+                let started = Instant::now();
+                tokio::time::timeout_at(deadline, self.fetch_data(Some(deadline)))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(TargetError::Timeout(
+                            deadline.saturating_duration_since(started),
+                        ))
+                    })
             }
         }
     }
+
+    async fn fetch_data(&self, deadline: Option<Instant>) -> Result<Vec<Fetched>, TargetError> {
+        let data = match &self {
+            Self::Http(config) => {
+                let body = config.fetch(deadline).await?;
+                return Ok(vec![Fetched {
+                    target: config.url.clone(),
+                    labels: Vec::new(),
+                    data: body.data,
+                    unchanged: body.unchanged,
+                }]);
+            }
+            Self::Exec(config) => config.fetch(deadline).await?,
+            Self::Tcp(config) => config.fetch_tcp().await?,
+            Self::Udp(config) => config.fetch_udp().await?,
+            Self::Sqlite(config) => config.fetch().await?,
+            Self::Push(config) => config.fetch()?,
+            Self::Redis(config) => config.fetch().await?,
+            Self::Static(data) => data.clone(),
+            Self::Directory(config) => config.fetch().await?,
+            Self::Tail(config) => config.fetch().await?,
+            Self::Stream(config) => config.fetch()?,
+            Self::S3(config) => config.fetch().await?,
+            Self::Sql(config) => config.fetch().await?,
+            Self::File(config) => return config.fetch().await,
+            Self::Mqtt(config) => return config.fetch(),
+        };
+
+        Ok(vec![Fetched {
+            target: self.describe().to_owned(),
+            labels: Vec::new(),
+            data,
+            unchanged: false,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fetch_fails_at_deadline() {
+        // accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = Target::Tcp(Box::new(socket::Config::new(
+            listener.local_addr().unwrap().to_string(),
+        )));
+
+        let started = Instant::now();
+        let result = target
+            .fetch(Some(started + Duration::from_millis(100)))
+            .await;
+
+        assert!(matches!(result, Err(TargetError::Timeout(..))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
 }
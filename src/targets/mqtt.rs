@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, SubscribeFilter};
+use tracing::warn;
+
+use super::{Fetched, TargetError};
+
+/// Last payload received on each topic.
+#[derive(Debug, Default)]
+pub struct Payloads {
+    topics: Mutex<BTreeMap<String, Bytes>>,
+    started: AtomicBool,
+}
+
+/// Target that subscribes to topic filters on an MQTT broker in the
+/// background, and keeps the last payload published to each topic.
+#[derive(Debug)]
+pub struct Config {
+    /// Url of the broker, without credentials.
+    pub url: String,
+    pub options: MqttOptions,
+    pub topics: Vec<String>,
+    pub qos: QoS,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub payloads: Payloads,
+}
+
+impl Config {
+    pub fn new(url: String, options: MqttOptions, topics: Vec<String>) -> Self {
+        Config {
+            url,
+            options,
+            topics,
+            qos: QoS::AtMostOnce,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            payloads: Payloads::default(),
+        }
+    }
+
+    /// Starts the background connection, unless it is already running.
+    pub fn start(self: &Arc<Self>) {
+        if !self.payloads.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(self.clone().run());
+        }
+    }
+
+    /// Returns the last payload of each topic, with the topic as `topic`
+    /// label, and fails if nothing was received yet.
+    pub fn fetch(self: &Arc<Self>) -> Result<Vec<Fetched>, TargetError> {
+        self.start();
+
+        let topics = self.payloads.topics.lock().unwrap();
+        if topics.is_empty() {
+            return Err(TargetError::NoData);
+        }
+
+        Ok(topics
+            .iter()
+            .map(|(topic, payload)| Fetched {
+                target: self.url.clone(),
+                labels: vec![(String::from("topic"), topic.clone())],
+                data: payload.clone(),
+                unchanged: false,
+            })
+            .collect())
+    }
+
+    async fn run(self: Arc<Self>) {
+        let (client, mut eventloop) = AsyncClient::new(self.options.clone(), 10);
+        let mut backoff = self.initial_backoff;
+
+        loop {
+            match eventloop.poll().await {
+                // the session is not persisted, so the subscriptions are
+                // renewed on every connect
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    backoff = self.initial_backoff;
+                    let filters = self
+                        .topics
+                        .iter()
+                        .map(|topic| SubscribeFilter::new(topic.clone(), self.qos));
+                    if let Err(err) = client.try_subscribe_many(filters) {
+                        warn!("failed to subscribe to {}: {}", self.url, err);
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    self.payloads
+                        .topics
+                        .lock()
+                        .unwrap()
+                        .insert(publish.topic, publish.payload);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!(
+                        "mqtt connection to {} failed, reconnecting in {:?}: {}",
+                        self.url, backoff, err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::targets::testing::wait_until;
+
+    // Minimal broker that accepts clients with the given credentials, and
+    // answers each subscription with the given publishes.
+    async fn serve(
+        credentials: Option<(&'static str, &'static str)>,
+        publishes: &'static [(&'static str, &'static str)],
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle(stream, credentials, publishes));
+            }
+        });
+        addr
+    }
+
+    async fn handle(
+        mut stream: TcpStream,
+        credentials: Option<(&str, &str)>,
+        publishes: &[(&str, &str)],
+    ) {
+        while let Some((kind, body)) = read_packet(&mut stream).await {
+            match kind >> 4 {
+                // CONNECT
+                1 => {
+                    let code = if connect_credentials(&body) == credentials {
+                        0
+                    } else {
+                        5
+                    };
+                    stream.write_all(&[0x20, 2, 0, code]).await.unwrap();
+                }
+                // SUBSCRIBE
+                8 => {
+                    stream
+                        .write_all(&[0x90, 3, body[0], body[1], 0])
+                        .await
+                        .unwrap();
+                    for (topic, payload) in publishes {
+                        stream.write_all(&publish(topic, payload)).await.unwrap();
+                    }
+                }
+                // PINGREQ
+                12 => stream.write_all(&[0xd0, 0]).await.unwrap(),
+                _ => {}
+            }
+        }
+    }
+
+    async fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let kind = stream.read_u8().await.ok()?;
+
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            let byte = stream.read_u8().await.ok()?;
+            len |= usize::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.ok()?;
+        Some((kind, body))
+    }
+
+    fn connect_credentials(body: &[u8]) -> Option<(&str, &str)> {
+        fn string(data: &[u8]) -> (&str, &[u8]) {
+            let len = usize::from(u16::from_be_bytes([data[0], data[1]]));
+            (
+                std::str::from_utf8(&data[2..2 + len]).unwrap(),
+                &data[2 + len..],
+            )
+        }
+
+        // protocol name, level, flags and keep alive
+        let flags = body[7];
+        let (_client_id, rest) = string(&body[10..]);
+        if flags & 0xc0 != 0xc0 {
+            return None;
+        }
+        let (username, rest) = string(rest);
+        let (password, _) = string(rest);
+        Some((username, password))
+    }
+
+    fn publish(topic: &str, payload: &str) -> Vec<u8> {
+        let len = 2 + topic.len() + payload.len();
+        assert!(len < 128);
+
+        let mut packet = vec![0x30, len as u8];
+        packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+        packet.extend_from_slice(topic.as_bytes());
+        packet.extend_from_slice(payload.as_bytes());
+        packet
+    }
+
+    fn config(addr: SocketAddr, credentials: Option<(&str, &str)>) -> Arc<Config> {
+        let mut options = MqttOptions::new("test", addr.ip().to_string(), addr.port());
+        if let Some((username, password)) = credentials {
+            options.set_credentials(username, password);
+        }
+
+        let config = Arc::new(Config {
+            initial_backoff: Duration::from_millis(10),
+            ..Config::new(
+                format!("mqtt://{}", addr),
+                options,
+                vec![String::from("sensors/#")],
+            )
+        });
+        config.start();
+        config
+    }
+
+    fn received(config: &Config) -> usize {
+        config.payloads.topics.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn test_last_payload_per_topic() {
+        let addr = serve(
+            None,
+            &[
+                ("sensors/a", r#"{"temperature": 20}"#),
+                ("sensors/b", r#"{"temperature": 30}"#),
+                ("sensors/a", r#"{"temperature": 21}"#),
+            ],
+        )
+        .await;
+
+        let config = config(addr, None);
+        wait_until(|| {
+            matches!(config.fetch(), Ok(fetched)
+                if fetched.len() == 2 && fetched[0].data == r#"{"temperature": 21}"#)
+        })
+        .await;
+
+        let fetched = config.fetch().unwrap();
+        let labels: Vec<_> = fetched.iter().map(|f| f.labels.clone()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                vec![(String::from("topic"), String::from("sensors/a"))],
+                vec![(String::from("topic"), String::from("sensors/b"))],
+            ]
+        );
+        assert_eq!(fetched[1].data, r#"{"temperature": 30}"#);
+        assert_eq!(fetched[0].target, format!("mqtt://{}", addr));
+    }
+
+    #[tokio::test]
+    async fn test_credentials() {
+        let addr = serve(Some(("exporter", "secret")), &[("sensors/a", "1")]).await;
+
+        let accepted = config(addr, Some(("exporter", "secret")));
+        wait_until(|| received(&accepted) == 1).await;
+        assert_eq!(accepted.fetch().unwrap()[0].data, "1");
+
+        let refused = config(addr, Some(("exporter", "wrong")));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(matches!(refused.fetch(), Err(TargetError::NoData)));
+    }
+}
@@ -0,0 +1,219 @@
+use bytes::Bytes;
+use reqwest::{header::HeaderMap, Url};
+
+use super::TargetError;
+
+#[derive(Debug)]
+pub enum NextPage {
+    /// Follow the RFC 5988 `Link` header with `rel="next"`.
+    LinkHeader,
+    /// jq query that extracts the url of the next page from the body.
+    Url { query: String },
+    /// jq query that extracts a cursor from the body, which is sent as the
+    /// query parameter `param` to get the next page.
+    Cursor { query: String, param: String },
+}
+
+#[derive(Debug)]
+pub struct Pagination {
+    pub next: NextPage,
+    pub max_pages: u32,
+}
+
+impl Pagination {
+    /// Returns the url of the page following the one fetched from `url`, or
+    /// `None` if it was the last page. The next page must have the same
+    /// origin, since it is fetched with the headers and credentials of the
+    /// target.
+    pub fn next_url(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> Result<Option<String>, TargetError> {
+        let current = Url::parse(url).map_err(|e| TargetError::Pagination(e.to_string()))?;
+
+        let next = match &self.next {
+            NextPage::LinkHeader => headers
+                .get_all(reqwest::header::LINK)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .find_map(next_link)
+                .map(|link| current.join(&link)),
+            NextPage::Url { query } => jq_string(query, body)?.map(|next| current.join(&next)),
+            NextPage::Cursor { query, param } => jq_string(query, body)?.map(|cursor| {
+                let mut next = current.clone();
+                next.query_pairs_mut()
+                    .clear()
+                    .extend_pairs(current.query_pairs().filter(|(k, _)| k != param))
+                    .append_pair(param, &cursor);
+                Ok(next)
+            }),
+        };
+
+        match next.transpose() {
+            Ok(Some(next)) if next.origin() != current.origin() => Err(TargetError::Pagination(
+                format!("next page {} is on a different origin than {}", next, url),
+            )),
+            Ok(next) => Ok(next.map(String::from)),
+            Err(err) => Err(TargetError::Pagination(err.to_string())),
+        }
+    }
+}
+
+/// Merges pages into a single JSON array. Elements of pages that are arrays
+/// are added to the result, any other page is added as an element.
+pub fn merge(pages: Vec<Bytes>) -> Result<Bytes, TargetError> {
+    let mut merged = Vec::new();
+
+    for page in pages {
+        match serde_json::from_slice(&page).map_err(|e| TargetError::Pagination(e.to_string()))? {
+            serde_json::Value::Array(items) => merged.extend(items),
+            value => merged.push(value),
+        }
+    }
+
+    serde_json::to_vec(&merged)
+        .map(Bytes::from)
+        .map_err(|e| TargetError::Pagination(e.to_string()))
+}
+
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let target = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+
+        parts
+            .filter_map(|param| param.trim().strip_prefix("rel="))
+            .any(|rel| {
+                rel.trim_matches('"')
+                    .split_whitespace()
+                    .any(|r| r == "next")
+            })
+            .then(|| target.to_owned())
+    })
+}
+
+// Runs a jq query that is expected to return a string, or null if there is
+// no next page.
+fn jq_string(query: &str, body: &Bytes) -> Result<Option<String>, TargetError> {
+    let input = std::str::from_utf8(body).map_err(|e| TargetError::Pagination(e.to_string()))?;
+    let output = jq_rs::compile(query)
+        .and_then(|mut program| program.run(input))
+        .map_err(|e| TargetError::Pagination(e.to_string()))?;
+
+    match serde_json::from_str(&output).map_err(|e| TargetError::Pagination(e.to_string()))? {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(s) if s.is_empty() => Ok(None),
+        serde_json::Value::String(s) => Ok(Some(s)),
+        serde_json::Value::Number(n) => Ok(Some(n.to_string())),
+        other => Err(TargetError::Pagination(format!(
+            "expected a string from {}, got {}",
+            query, other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_link() {
+        let header = r#"<https://api.example.com/items?page=1>; rel="prev", <https://api.example.com/items?page=3>; rel="next""#;
+
+        assert_eq!(
+            next_link(header),
+            Some("https://api.example.com/items?page=3".to_owned())
+        );
+        assert_eq!(
+            next_link(r#"<https://api.example.com/items?page=1>; rel="prev""#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cursor_replaces_param() {
+        let pagination = Pagination {
+            next: NextPage::Cursor {
+                query: ".next".to_owned(),
+                param: "cursor".to_owned(),
+            },
+            max_pages: 10,
+        };
+
+        let next = pagination
+            .next_url(
+                "https://api.example.com/items?limit=10&cursor=a",
+                &HeaderMap::new(),
+                &Bytes::from(r#"{"next": "b"}"#),
+            )
+            .unwrap();
+
+        assert_eq!(
+            next,
+            Some("https://api.example.com/items?limit=10&cursor=b".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_relative_next_url() {
+        let pagination = Pagination {
+            next: NextPage::Url {
+                query: ".links.next".to_owned(),
+            },
+            max_pages: 10,
+        };
+
+        let next = |body: &'static str| {
+            pagination.next_url(
+                "https://api.example.com/v1/items",
+                &HeaderMap::new(),
+                &Bytes::from(body),
+            )
+        };
+
+        assert_eq!(
+            next(r#"{"links": {"next": "/v1/items?page=2"}}"#).unwrap(),
+            Some("https://api.example.com/v1/items?page=2".to_owned())
+        );
+        assert_eq!(next(r#"{"links": {"next": null}}"#).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rejects_other_origin() {
+        let pagination = Pagination {
+            next: NextPage::LinkHeader,
+            max_pages: 10,
+        };
+
+        let next = |link: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(reqwest::header::LINK, link.parse().unwrap());
+            pagination.next_url("https://api.example.com/items", &headers, &Bytes::new())
+        };
+
+        for link in [
+            r#"<https://attacker.example.com/items?page=2>; rel="next""#,
+            r#"<http://api.example.com/items?page=2>; rel="next""#,
+            r#"<https://api.example.com:8443/items?page=2>; rel="next""#,
+        ] {
+            assert!(matches!(next(link), Err(TargetError::Pagination(_))));
+        }
+        assert_eq!(
+            next(r#"<https://api.example.com/items?page=2>; rel="next""#).unwrap(),
+            Some("https://api.example.com/items?page=2".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let merged = merge(vec![
+            Bytes::from(r#"[{"a": 1}, {"a": 2}]"#),
+            Bytes::from(r#"{"a": 3}"#),
+        ])
+        .unwrap();
+
+        assert_eq!(merged, r#"[{"a":1},{"a":2},{"a":3}]"#);
+    }
+}
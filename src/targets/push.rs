@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bytes::{Bytes, BytesMut};
+
+use super::{limit::LimitedBuffer, TargetError};
+
+/// Limit for the size of a pushed body if none is configured, the same as the
+/// default limit of axum.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 2 << 20;
+
+/// Pushed bodies with the time they were received.
+#[derive(Debug, Default)]
+pub struct Bodies(Mutex<VecDeque<(Instant, Bytes)>>);
+
+/// Target for data that is pushed to the exporter with `POST /push/<name>`
+/// instead of being fetched.
+#[derive(Debug)]
+pub struct Config {
+    pub name: String,
+    /// Number of pushed bodies to keep, they are joined with newlines.
+    pub max_bodies: usize,
+    /// Pushed bodies older than this are dropped.
+    pub expiry: Option<Duration>,
+    pub max_body_size: Option<u64>,
+    pub bodies: Bodies,
+}
+
+impl Config {
+    pub fn new(name: String) -> Self {
+        Config {
+            name,
+            max_bodies: 1,
+            expiry: None,
+            max_body_size: Some(DEFAULT_MAX_BODY_SIZE),
+            bodies: Bodies::default(),
+        }
+    }
+
+    pub fn push(&self, body: Bytes) -> Result<(), TargetError> {
+        LimitedBuffer::new(self.max_body_size).check_size(body.len() as u64)?;
+
+        let mut bodies = self.bodies.0.lock().unwrap();
+        bodies.push_back((Instant::now(), body));
+        while bodies.len() > self.max_bodies {
+            bodies.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the pushed bodies that have not expired yet, and fails if
+    /// there are none.
+    pub fn fetch(&self) -> Result<Bytes, TargetError> {
+        let mut bodies = self.bodies.0.lock().unwrap();
+        if let Some(expiry) = self.expiry {
+            bodies.retain(|(received, _)| received.elapsed() < expiry);
+        }
+
+        match bodies.len() {
+            0 => Err(TargetError::NoData),
+            1 => Ok(bodies[0].1.clone()),
+            _ => {
+                let mut data = BytesMut::new();
+                for (i, (_, body)) in bodies.iter().enumerate() {
+                    if i > 0 {
+                        data.extend_from_slice(b"\n");
+                    }
+                    data.extend_from_slice(body);
+                }
+                Ok(data.freeze())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_body() {
+        let config = Config::new("job".to_owned());
+        assert!(matches!(config.fetch(), Err(TargetError::NoData)));
+
+        config.push(Bytes::from("1")).unwrap();
+        config.push(Bytes::from("2")).unwrap();
+
+        assert_eq!(config.fetch().unwrap(), "2");
+        assert_eq!(config.fetch().unwrap(), "2");
+    }
+
+    #[test]
+    fn test_bounded_queue() {
+        let config = Config {
+            max_bodies: 2,
+            ..Config::new("job".to_owned())
+        };
+
+        for body in ["1", "2", "3"] {
+            config.push(Bytes::from(body)).unwrap();
+        }
+
+        assert_eq!(config.fetch().unwrap(), "2\n3");
+    }
+
+    #[test]
+    fn test_expiry() {
+        let config = Config {
+            expiry: Some(Duration::from_millis(50)),
+            ..Config::new("job".to_owned())
+        };
+
+        config.push(Bytes::from("1")).unwrap();
+        assert_eq!(config.fetch().unwrap(), "1");
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(matches!(config.fetch(), Err(TargetError::NoData)));
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        let config = Config {
+            max_body_size: Some(1),
+            ..Config::new("job".to_owned())
+        };
+
+        assert!(matches!(
+            config.push(Bytes::from("12")),
+            Err(TargetError::BodyTooLarge(1))
+        ));
+        assert!(matches!(config.fetch(), Err(TargetError::NoData)));
+    }
+}
@@ -0,0 +1,332 @@
+use std::{fmt, time::Duration};
+
+use bytes::Bytes;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use tokio::sync::OnceCell;
+
+use super::{convert, limit::LimitedBuffer, TargetError};
+
+#[derive(Debug)]
+pub enum Command {
+    /// Value of a single key, as is.
+    Get(String),
+    /// Values of several keys, as a JSON object keyed by key.
+    Mget(Vec<String>),
+    /// Fields of a hash, as a JSON object.
+    Hgetall(String),
+    /// Server information as text, optionally of a single section.
+    Info(Option<String>),
+    /// Values of all keys matching a pattern, as a JSON object keyed by key.
+    Scan(String),
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// Address of the server, without credentials from the url.
+    pub address: String,
+    pub command: Command,
+    pub timeout: Option<Duration>,
+    /// Replies are read in full by the client, so the limit is checked on
+    /// the returned data.
+    pub max_body_size: Option<u64>,
+    pub client: redis::Client,
+    pub connection: SharedConnection,
+}
+
+/// Connection opened by the first fetch and reused by the next ones, it
+/// reconnects by itself when the server closes it.
+#[derive(Default)]
+pub struct SharedConnection(OnceCell<ConnectionManager>);
+
+impl fmt::Debug for SharedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedConnection")
+            .field(&self.0.initialized())
+            .finish()
+    }
+}
+
+impl Config {
+    pub fn new(client: redis::Client, command: Command) -> Self {
+        Config {
+            address: client.get_connection_info().addr.to_string(),
+            command,
+            timeout: Some(Duration::from_secs(10)),
+            max_body_size: None,
+            client,
+            connection: SharedConnection::default(),
+        }
+    }
+
+    pub async fn fetch(&self) -> Result<Bytes, TargetError> {
+        let data = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run())
+                .await
+                .unwrap_or(Err(TargetError::Timeout(timeout)))?,
+            None => self.run().await?,
+        };
+
+        LimitedBuffer::new(self.max_body_size).check_size(data.len() as u64)?;
+        Ok(data)
+    }
+
+    async fn run(&self) -> Result<Bytes, TargetError> {
+        let mut conn = self
+            .connection
+            .0
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?
+            .clone();
+
+        match &self.command {
+            Command::Get(key) => {
+                let value: Option<Vec<u8>> = conn.get(key).await?;
+                value.map(Bytes::from).ok_or(TargetError::NoData)
+            }
+            Command::Mget(keys) => json(mget(&mut conn, keys).await?),
+            Command::Hgetall(key) => {
+                let fields: Vec<(String, String)> = conn.hgetall(key).await?;
+                json(
+                    fields
+                        .into_iter()
+                        .map(|(field, value)| (field, Some(value)))
+                        .collect(),
+                )
+            }
+            Command::Info(section) => {
+                let mut cmd = redis::cmd("INFO");
+                if let Some(section) = section {
+                    cmd.arg(section);
+                }
+                let info: String = cmd.query_async(&mut conn).await?;
+                Ok(Bytes::from(info))
+            }
+            Command::Scan(pattern) => {
+                let mut keys = Vec::new();
+                let mut iter = conn.scan_match::<_, String>(pattern).await?;
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                drop(iter);
+
+                json(mget(&mut conn, &keys).await?)
+            }
+        }
+    }
+}
+
+async fn mget(
+    conn: &mut ConnectionManager,
+    keys: &[String],
+) -> Result<Vec<(String, Option<String>)>, TargetError> {
+    // MGET fails without any keys
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let values: Vec<Option<String>> = redis::cmd("MGET").arg(keys).query_async(conn).await?;
+    Ok(keys.iter().cloned().zip(values).collect())
+}
+
+// Keys without a value, e.g. because they hold a hash, become null.
+fn json(entries: Vec<(String, Option<String>)>) -> Result<Bytes, TargetError> {
+    let object: serde_json::Map<String, serde_json::Value> = entries
+        .into_iter()
+        .map(|(key, value)| (key, value.map_or(serde_json::Value::Null, convert::number)))
+        .collect();
+
+    Ok(Bytes::from(
+        serde_json::to_vec(&object).expect("object is a valid json value"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    // Minimal server speaking the redis protocol, that answers each command
+    // with the reply returned by `reply`.
+    async fn serve(reply: fn(&[String]) -> String) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle(stream, reply));
+            }
+        });
+        addr
+    }
+
+    async fn handle(stream: TcpStream, reply: fn(&[String]) -> String) {
+        let mut stream = BufReader::new(stream);
+        let mut line = String::new();
+
+        while stream.read_line(&mut line).await.unwrap() > 0 {
+            let args: usize = line.trim()[1..].parse().unwrap();
+            let mut command = Vec::new();
+
+            for _ in 0..args {
+                line.clear();
+                stream.read_line(&mut line).await.unwrap();
+                let len: usize = line.trim()[1..].parse().unwrap();
+
+                let mut arg = vec![0; len + 2];
+                stream.read_exact(&mut arg).await.unwrap();
+                arg.truncate(len);
+                command.push(String::from_utf8(arg).unwrap());
+            }
+
+            stream
+                .get_mut()
+                .write_all(reply(&command).as_bytes())
+                .await
+                .unwrap();
+            line.clear();
+        }
+    }
+
+    fn bulk(value: &str) -> String {
+        format!("${}\r\n{}\r\n", value.len(), value)
+    }
+
+    fn config(addr: SocketAddr, command: Command) -> Config {
+        let client = redis::Client::open(format!("redis://{}/", addr)).unwrap();
+        Config::new(client, command)
+    }
+
+    #[tokio::test]
+    async fn test_get() {
+        let addr = serve(|command| match command {
+            [cmd, key] if cmd == "GET" && key == "status" => bulk(r#"{"up": 1}"#),
+            _ => "$-1\r\n".to_owned(),
+        })
+        .await;
+
+        let get = |key: &str| config(addr, Command::Get(key.to_owned()));
+
+        assert_eq!(get("status").fetch().await.unwrap(), r#"{"up": 1}"#);
+        assert!(matches!(
+            get("missing").fetch().await,
+            Err(TargetError::NoData)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let addr = serve(|_| bulk("0123456789")).await;
+
+        let config = Config {
+            max_body_size: Some(9),
+            ..config(addr, Command::Get("status".into()))
+        };
+
+        assert!(matches!(
+            config.fetch().await,
+            Err(TargetError::BodyTooLarge(9))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mget() {
+        let addr = serve(|command| match command {
+            [cmd, ..] if cmd == "MGET" => format!("*3\r\n{}{}$-1\r\n", bulk("42"), bulk("on")),
+            _ => "-ERR unexpected\r\n".to_owned(),
+        })
+        .await;
+
+        let config = config(
+            addr,
+            Command::Mget(vec!["jobs".into(), "flag".into(), "missing".into()]),
+        );
+
+        assert_eq!(
+            config.fetch().await.unwrap(),
+            r#"{"flag":"on","jobs":42,"missing":null}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hgetall() {
+        let addr = serve(|command| match command {
+            [cmd, key] if cmd == "HGETALL" && key == "queue" => format!(
+                "*4\r\n{}{}{}{}",
+                bulk("pending"),
+                bulk("3"),
+                bulk("latency"),
+                bulk("0.25")
+            ),
+            _ => "-ERR unexpected\r\n".to_owned(),
+        })
+        .await;
+
+        let config = config(addr, Command::Hgetall("queue".into()));
+
+        assert_eq!(
+            config.fetch().await.unwrap(),
+            r#"{"latency":0.25,"pending":3}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let addr = serve(|command| match command {
+            [cmd, section] if cmd == "INFO" && section == "clients" => {
+                bulk("# Clients\r\nconnected_clients:2\r\n")
+            }
+            _ => "-ERR unexpected\r\n".to_owned(),
+        })
+        .await;
+
+        let config = config(addr, Command::Info(Some("clients".into())));
+
+        assert_eq!(
+            config.fetch().await.unwrap(),
+            "# Clients\r\nconnected_clients:2\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let addr = serve(|command| match command {
+            [cmd, cursor, _, pattern] if cmd == "SCAN" && pattern == "jobs:*" => match &**cursor {
+                "0" => format!("*2\r\n{}*1\r\n{}", bulk("7"), bulk("jobs:a")),
+                _ => format!("*2\r\n{}*1\r\n{}", bulk("0"), bulk("jobs:b")),
+            },
+            [cmd, a, b] if cmd == "MGET" && a == "jobs:a" && b == "jobs:b" => {
+                format!("*2\r\n{}{}", bulk("1"), bulk("2"))
+            }
+            _ => "-ERR unexpected\r\n".to_owned(),
+        })
+        .await;
+
+        let config = config(addr, Command::Scan("jobs:*".into()));
+
+        assert_eq!(config.fetch().await.unwrap(), r#"{"jobs:a":1,"jobs:b":2}"#);
+    }
+
+    #[tokio::test]
+    async fn test_reuses_connection() {
+        // accepts a single connection, so that the second fetch fails if it
+        // opens another one
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(listener);
+            handle(stream, |_| bulk("1")).await;
+        });
+
+        let config = config(addr, Command::Get("status".into()));
+
+        assert_eq!(config.fetch().await.unwrap(), "1");
+        assert_eq!(config.fetch().await.unwrap(), "1");
+    }
+}
@@ -0,0 +1,224 @@
+use std::{future::Future, time::Duration};
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::TargetError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryOn {
+    /// Failures to establish a connection.
+    Connect,
+    /// Requests that did not complete within the configured timeout.
+    Timeout,
+    /// Any other failure while sending a request or reading its response, e.g.
+    /// a connection reset.
+    Request,
+}
+
+#[derive(Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub retry_on: Vec<RetryOn>,
+    pub status_codes: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_on: vec![RetryOn::Connect, RetryOn::Timeout, RetryOn::Request],
+            status_codes: vec![429, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Calls `fetch` until it succeeds, fails with an error that should not be
+    /// retried, or `max_attempts` is reached. Backoff doubles between attempts
+    /// up to `max_backoff`. Attempts are cancelled at the `deadline`, and no
+    /// retry is made if sleeping would pass it.
+    pub async fn run<F, Fut, T>(
+        &self,
+        deadline: Option<Instant>,
+        fetch: F,
+    ) -> Result<T, TargetError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, TargetError>>,
+    {
+        let started = Instant::now();
+        let mut attempt = 1;
+        let mut backoff = self.initial_backoff;
+
+        loop {
+            let result = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, fetch())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(TargetError::Timeout(
+                            deadline.saturating_duration_since(started),
+                        ))
+                    }),
+                None => fetch().await,
+            };
+
+            match result {
+                Err(err) if attempt < self.max_attempts && self.should_retry(&err) => {
+                    let sleep_until = Instant::now() + backoff;
+                    if deadline
+                        .filter(|deadline| sleep_until >= *deadline)
+                        .is_some()
+                    {
+                        return Err(err);
+                    }
+
+                    debug!(
+                        "attempt {} failed, retrying in {:?}: {:?}",
+                        attempt, backoff, err
+                    );
+                    tokio::time::sleep(backoff).await;
+
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn should_retry(&self, err: &TargetError) -> bool {
+        match err {
+            TargetError::Status(status) => self.status_codes.contains(&status.as_u16()),
+            TargetError::HTTP(err) => {
+                let kind = if err.is_connect() {
+                    RetryOn::Connect
+                } else if err.is_timeout() {
+                    RetryOn::Timeout
+                } else {
+                    RetryOn::Request
+                };
+                self.retry_on.contains(&kind)
+            }
+            TargetError::Socket(err) => {
+                let kind = if err.is_connect() {
+                    RetryOn::Connect
+                } else {
+                    RetryOn::Request
+                };
+                self.retry_on.contains(&kind)
+            }
+            TargetError::Timeout(_) => self.retry_on.contains(&RetryOn::Timeout),
+            TargetError::IO(_)
+            | TargetError::Pagination(_)
+            | TargetError::BodyTooLarge(_)
+            | TargetError::Exit(..)
+            | TargetError::Sqlite(_)
+            | TargetError::Redis(_)
+            | TargetError::S3(_)
+            | TargetError::Sql(_)
+            | TargetError::NoData => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    async fn run(
+        policy: &RetryPolicy,
+        deadline: Option<Instant>,
+        status: reqwest::StatusCode,
+    ) -> (Result<(), TargetError>, u32) {
+        let attempts = AtomicU32::new(0);
+        let result = policy
+            .run(deadline, || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(TargetError::Status(status))
+            })
+            .await;
+
+        (result, attempts.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_max_attempts() {
+        let (result, attempts) = run(&policy(), None, reqwest::StatusCode::BAD_GATEWAY).await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_other_status_codes() {
+        let (_, attempts) = run(&policy(), None, reqwest::StatusCode::NOT_FOUND).await;
+
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_deadline() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            ..RetryPolicy::default()
+        };
+        let deadline = Instant::now() + Duration::from_millis(500);
+
+        let (_, attempts) = run(&policy, Some(deadline), reqwest::StatusCode::BAD_GATEWAY).await;
+
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancels_attempt_at_deadline() {
+        let attempts = AtomicU32::new(0);
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(200);
+
+        let result = policy()
+            .run(Some(deadline), || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err(TargetError::Status(reqwest::StatusCode::BAD_GATEWAY));
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await;
+
+        assert!(matches!(result, Err(TargetError::Timeout(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_returns_first_success() {
+        let attempts = AtomicU32::new(0);
+        let result = policy()
+            .run(None, || async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(TargetError::Status(
+                        reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                    )),
+                    _ => Ok("data"),
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), "data");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,302 @@
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+use super::{limit::LimitedBuffer, TargetError};
+
+// large enough for any udp datagram
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Request-response exchange over a raw tcp connection or udp socket, for
+/// simple line protocols like memcached `stats` or Redis `INFO`.
+#[derive(Debug)]
+pub struct Config {
+    /// `host:port` to connect to.
+    pub address: String,
+    pub payload: Option<Bytes>,
+    /// Stop reading once this sequence is received, it is not part of the
+    /// returned data.
+    pub delimiter: Option<Bytes>,
+    /// Fail if the response grows over this many bytes.
+    pub max_body_size: Option<u64>,
+    /// Stop reading when no data is received for this long.
+    pub read_timeout: Option<Duration>,
+    /// Fail if the whole exchange takes longer than this.
+    pub timeout: Option<Duration>,
+}
+
+impl Config {
+    pub fn new(address: String) -> Self {
+        Config {
+            address,
+            payload: None,
+            delimiter: None,
+            max_body_size: None,
+            read_timeout: None,
+            timeout: None,
+        }
+    }
+
+    /// Connects, sends the payload and reads the response until the server
+    /// closes the connection or one of `delimiter` and `read_timeout` ends
+    /// it.
+    pub async fn fetch_tcp(&self) -> Result<Bytes, TargetError> {
+        self.with_timeout(async {
+            let mut stream = TcpStream::connect(&self.address).await?;
+            if let Some(payload) = &self.payload {
+                stream.write_all(payload).await?;
+            }
+
+            let mut response = Response::new(self);
+            let mut chunk = vec![0; CHUNK_SIZE];
+            while let Some(n) = self.read(stream.read(&mut chunk)).await? {
+                if n == 0 || response.push(&chunk[..n])? {
+                    break;
+                }
+            }
+
+            Ok(response.into_bytes())
+        })
+        .await
+    }
+
+    /// Sends the payload as a single datagram and reads datagrams until one of
+    /// `delimiter` and `read_timeout` ends the response. Only the first
+    /// datagram is read if neither `delimiter` nor `read_timeout` is set.
+    pub async fn fetch_udp(&self) -> Result<Bytes, TargetError> {
+        self.with_timeout(async {
+            let address = tokio::net::lookup_host(&self.address)
+                .await?
+                .next()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("could not resolve {}", self.address),
+                    )
+                })?;
+            let local = match address {
+                SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+                SocketAddr::V6(_) => SocketAddr::from(([0; 8], 0)),
+            };
+
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(address).await?;
+            socket
+                .send(self.payload.as_deref().unwrap_or_default())
+                .await?;
+
+            let mut response = Response::new(self);
+            let mut datagram = vec![0; CHUNK_SIZE];
+            while let Some(n) = self.read(socket.recv(&mut datagram)).await? {
+                if response.push(&datagram[..n])?
+                    || (self.delimiter.is_none() && self.read_timeout.is_none())
+                {
+                    break;
+                }
+            }
+
+            Ok(response.into_bytes())
+        })
+        .await
+    }
+
+    async fn with_timeout<F>(&self, exchange: F) -> Result<Bytes, TargetError>
+    where
+        F: Future<Output = Result<Bytes, TargetError>>,
+    {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange)
+                .await
+                .unwrap_or(Err(TargetError::Timeout(timeout))),
+            None => exchange.await,
+        }
+    }
+
+    // Returns `None` if no data arrived within `read_timeout`.
+    async fn read<F>(&self, read: F) -> Result<Option<usize>, TargetError>
+    where
+        F: Future<Output = std::io::Result<usize>>,
+    {
+        match self.read_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, read).await {
+                Ok(n) => Ok(Some(n?)),
+                Err(_) => Ok(None),
+            },
+            None => Ok(Some(read.await?)),
+        }
+    }
+}
+
+struct Response<'a> {
+    buffer: BytesMut,
+    delimiter: Option<&'a [u8]>,
+    limit: Option<u64>,
+}
+
+impl<'a> Response<'a> {
+    fn new(config: &'a Config) -> Self {
+        Response {
+            buffer: BytesMut::new(),
+            delimiter: config.delimiter.as_deref(),
+            limit: config.max_body_size,
+        }
+    }
+
+    /// Adds received data, and returns whether the response is complete.
+    /// Fails if the response without the delimiter exceeds the limit.
+    fn push(&mut self, chunk: &[u8]) -> Result<bool, TargetError> {
+        self.buffer.extend_from_slice(chunk);
+        let mut complete = false;
+
+        if let Some(delimiter) = self.delimiter {
+            if let Some(pos) = self
+                .buffer
+                .windows(delimiter.len())
+                .position(|window| window == delimiter)
+            {
+                self.buffer.truncate(pos);
+                complete = true;
+            }
+        }
+
+        LimitedBuffer::new(self.limit).check_size(self.buffer.len() as u64)?;
+        Ok(complete)
+    }
+
+    fn into_bytes(self) -> Bytes {
+        self.buffer.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // Accepts a single connection, checks the request and writes `response`
+    // without closing the connection.
+    async fn serve_tcp(request: &'static [u8], response: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = vec![0; request.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, request);
+
+            stream.write_all(response).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+        addr
+    }
+
+    fn config(addr: SocketAddr) -> Config {
+        Config {
+            payload: Some(Bytes::from("stats\r\n")),
+            ..Config::new(addr.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_until_eof() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            stream.write_all(b"imok").await.unwrap();
+        });
+
+        let config = Config {
+            payload: Some(Bytes::from("ruok")),
+            ..Config::new(addr.to_string())
+        };
+
+        assert_eq!(config.fetch_tcp().await.unwrap(), "imok");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_until_delimiter() {
+        let addr = serve_tcp(b"stats\r\n", b"STAT pid 1\r\nSTAT uptime 2\r\nEND\r\n").await;
+
+        let config = Config {
+            delimiter: Some(Bytes::from("END\r\n")),
+            ..config(addr)
+        };
+
+        assert_eq!(
+            config.fetch_tcp().await.unwrap(),
+            "STAT pid 1\r\nSTAT uptime 2\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tcp_max_body_size() {
+        let addr = serve_tcp(b"stats\r\n", b"0123456789").await;
+
+        let config = Config {
+            max_body_size: Some(4),
+            ..config(addr)
+        };
+
+        assert!(matches!(
+            config.fetch_tcp().await,
+            Err(TargetError::BodyTooLarge(4))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_until_read_timeout() {
+        let addr = serve_tcp(b"stats\r\n", b"STAT pid 1\r\n").await;
+
+        let config = Config {
+            read_timeout: Some(Duration::from_millis(100)),
+            ..config(addr)
+        };
+
+        assert_eq!(config.fetch_tcp().await.unwrap(), "STAT pid 1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_timeout() {
+        let addr = serve_tcp(b"stats\r\n", b"STAT pid 1\r\n").await;
+
+        let config = Config {
+            timeout: Some(Duration::from_millis(100)),
+            ..config(addr)
+        };
+
+        assert!(matches!(
+            config.fetch_tcp().await,
+            Err(TargetError::Timeout(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut request = vec![0; CHUNK_SIZE];
+            let (n, peer) = server.recv_from(&mut request).await.unwrap();
+            assert_eq!(&request[..n], b"status");
+
+            server.send_to(b"up ", peer).await.unwrap();
+            server.send_to(b"ignored", peer).await.unwrap();
+        });
+
+        let config = Config {
+            payload: Some(Bytes::from("status")),
+            ..Config::new(addr.to_string())
+        };
+
+        assert_eq!(config.fetch_udp().await.unwrap(), "up ");
+    }
+}
@@ -0,0 +1,296 @@
+use std::{str::FromStr, time::Duration};
+
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use serde_json::Value;
+use sqlx::{
+    mysql::{MySqlConnectOptions, MySqlPool, MySqlPoolOptions, MySqlRow},
+    postgres::{PgConnectOptions, PgPool, PgPoolOptions, PgRow},
+    types::Decimal,
+    Column, Executor, Row, TypeInfo,
+};
+
+use super::{convert, limit::LimitedJsonArray, TargetError};
+
+#[derive(Debug)]
+pub enum Pool {
+    Postgres(PgPool),
+    MySql(MySqlPool),
+}
+
+/// Target that runs a query on a postgres or mysql database. Connections
+/// are opened when needed and kept in the pool between scrapes.
+#[derive(Debug)]
+pub struct Config {
+    /// DSN of the database, without the password.
+    pub dsn: String,
+    pub query: String,
+    /// Limit for a fetch, including waiting for a connection. The statement
+    /// timeout on the server is set when a connection is opened.
+    pub timeout: Option<Duration>,
+    pub max_body_size: Option<u64>,
+    pub pool: Pool,
+}
+
+impl Pool {
+    /// Creates a pool that opens connections when needed. The password from
+    /// `password` takes precedence over the one in `dsn`.
+    pub fn postgres(
+        dsn: &str,
+        password: Option<&str>,
+        max_connections: u32,
+        statement_timeout: Option<Duration>,
+    ) -> Result<Self, sqlx::Error> {
+        let mut options = PgConnectOptions::from_str(dsn)?;
+        if let Some(password) = password {
+            options = options.password(password);
+        }
+        if let Some(timeout) = statement_timeout {
+            options = options.options([("statement_timeout", timeout.as_millis().to_string())]);
+        }
+
+        Ok(Pool::Postgres(
+            PgPoolOptions::new()
+                .max_connections(max_connections)
+                .connect_lazy_with(options),
+        ))
+    }
+
+    /// Like `Pool::postgres`, the statement timeout is set with
+    /// `max_execution_time`, which only applies to `SELECT` statements.
+    pub fn mysql(
+        dsn: &str,
+        password: Option<&str>,
+        max_connections: u32,
+        statement_timeout: Option<Duration>,
+    ) -> Result<Self, sqlx::Error> {
+        let mut options = MySqlConnectOptions::from_str(dsn)?;
+        if let Some(password) = password {
+            options = options.password(password);
+        }
+
+        let mut pool = MySqlPoolOptions::new().max_connections(max_connections);
+        if let Some(timeout) = statement_timeout {
+            let set_timeout = format!("SET SESSION max_execution_time = {}", timeout.as_millis());
+            pool = pool.after_connect(move |conn, _| {
+                let set_timeout = set_timeout.clone();
+                Box::pin(async move {
+                    conn.execute(set_timeout.as_str()).await?;
+                    Ok(())
+                })
+            });
+        }
+
+        Ok(Pool::MySql(pool.connect_lazy_with(options)))
+    }
+}
+
+impl Config {
+    pub fn new(dsn: String, query: String, pool: Pool) -> Self {
+        Config {
+            dsn,
+            query,
+            timeout: None,
+            max_body_size: None,
+            pool,
+        }
+    }
+
+    /// Runs the query and returns the rows as a JSON array of objects keyed
+    /// by column name.
+    pub async fn fetch(&self) -> Result<Bytes, TargetError> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run())
+                .await
+                .unwrap_or(Err(TargetError::Timeout(timeout))),
+            None => self.run().await,
+        }
+    }
+
+    async fn run(&self) -> Result<Bytes, TargetError> {
+        match &self.pool {
+            Pool::Postgres(pool) => {
+                self.json_rows(sqlx::query(&self.query).fetch(pool), postgres_value)
+                    .await
+            }
+            Pool::MySql(pool) => {
+                self.json_rows(sqlx::query(&self.query).fetch(pool), mysql_value)
+                    .await
+            }
+        }
+    }
+
+    // Rows are converted as they are received, so that the query is
+    // abandoned as soon as the result grows over `max_body_size`.
+    async fn json_rows<R, S>(&self, mut rows: S, value: ValueFn<R>) -> Result<Bytes, TargetError>
+    where
+        R: Row,
+        S: Stream<Item = Result<R, sqlx::Error>> + Unpin,
+    {
+        let mut result = LimitedJsonArray::new(self.max_body_size);
+        while let Some(row) = rows.try_next().await? {
+            let mut object = serde_json::Map::new();
+            for column in row.columns() {
+                let value = value(&row, column.ordinal(), column.type_info().name())?;
+                object.insert(column.name().to_owned(), value);
+            }
+            result.push(&Value::Object(object))?;
+        }
+
+        result.into_bytes()
+    }
+}
+
+type ValueFn<R> = fn(&R, usize, &str) -> Result<Value, sqlx::Error>;
+
+fn postgres_value(row: &PgRow, i: usize, type_name: &str) -> Result<Value, sqlx::Error> {
+    Ok(match type_name {
+        "BOOL" => json(row.try_get::<Option<bool>, _>(i)?),
+        "INT2" => json(row.try_get::<Option<i16>, _>(i)?),
+        "INT4" => json(row.try_get::<Option<i32>, _>(i)?),
+        "INT8" => json(row.try_get::<Option<i64>, _>(i)?),
+        "FLOAT4" => json(row.try_get::<Option<f32>, _>(i)?),
+        "FLOAT8" => json(row.try_get::<Option<f64>, _>(i)?),
+        "NUMERIC" => decimal(row.try_get(i)?),
+        "TEXT" | "VARCHAR" | "CHAR" | "NAME" => json(row.try_get::<Option<String>, _>(i)?),
+        "JSON" | "JSONB" => row.try_get::<Option<Value>, _>(i)?.unwrap_or(Value::Null),
+        _ => return Err(unsupported(i, type_name)),
+    })
+}
+
+fn mysql_value(row: &MySqlRow, i: usize, type_name: &str) -> Result<Value, sqlx::Error> {
+    Ok(match type_name {
+        "NULL" => Value::Null,
+        "BOOLEAN" => json(row.try_get::<Option<bool>, _>(i)?),
+        "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => {
+            json(row.try_get::<Option<i64>, _>(i)?)
+        }
+        "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "MEDIUMINT UNSIGNED" | "INT UNSIGNED"
+        | "BIGINT UNSIGNED" => json(row.try_get::<Option<u64>, _>(i)?),
+        "FLOAT" => json(row.try_get::<Option<f32>, _>(i)?),
+        "DOUBLE" => json(row.try_get::<Option<f64>, _>(i)?),
+        "DECIMAL" => decimal(row.try_get(i)?),
+        "CHAR" | "VARCHAR" | "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" | "ENUM"
+        | "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" => row
+            .try_get::<Option<Vec<u8>>, _>(i)?
+            .map_or(Value::Null, |text| convert::text(&text)),
+        "JSON" => row.try_get::<Option<Value>, _>(i)?.unwrap_or(Value::Null),
+        _ => return Err(unsupported(i, type_name)),
+    })
+}
+
+fn json<T: Into<Value>>(value: Option<T>) -> Value {
+    value.map_or(Value::Null, Into::into)
+}
+
+fn decimal(value: Option<Decimal>) -> Value {
+    value.map_or(Value::Null, |d| convert::number(d.to_string()))
+}
+
+fn unsupported(i: usize, type_name: &str) -> sqlx::Error {
+    sqlx::Error::ColumnDecode {
+        index: i.to_string(),
+        source: format!(
+            "unsupported type {}, cast the column to a supported type in the query",
+            type_name
+        )
+        .into(),
+    }
+}
+
+// These tests need a database, they are ignored unless run with `--ignored`
+// and the DSN of a test database set in DATA_EXPORTER_TEST_POSTGRES or
+// DATA_EXPORTER_TEST_MYSQL.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn postgres(query: &str) -> Config {
+        let dsn = std::env::var("DATA_EXPORTER_TEST_POSTGRES")
+            .expect("DATA_EXPORTER_TEST_POSTGRES is not set");
+        let pool = Pool::postgres(&dsn, None, 1, Some(Duration::from_millis(100))).unwrap();
+        Config::new(dsn, query.to_owned(), pool)
+    }
+
+    fn mysql(query: &str) -> Config {
+        let dsn =
+            std::env::var("DATA_EXPORTER_TEST_MYSQL").expect("DATA_EXPORTER_TEST_MYSQL is not set");
+        let pool = Pool::mysql(&dsn, None, 1, Some(Duration::from_millis(100))).unwrap();
+        Config::new(dsn, query.to_owned(), pool)
+    }
+
+    async fn fetch(config: &Config) -> Value {
+        serde_json::from_slice(&config.fetch().await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database"]
+    async fn test_postgres_rows_as_json() {
+        let config = postgres(
+            "SELECT * FROM (VALUES
+                ('sync'::text, 3::int8, 1.5::float8, 12.50::numeric, true, NULL::text, '{\"a\": 1}'::jsonb),
+                ('backup', 1, 20.25, 0.1, false, 'disk full', NULL)
+            ) AS jobs (name, attempts, duration, cost, ok, error, extra) ORDER BY name",
+        );
+
+        assert_eq!(
+            fetch(&config).await,
+            serde_json::json!([
+                {"name": "backup", "attempts": 1, "duration": 20.25, "cost": 0.1, "ok": false, "error": "disk full", "extra": null},
+                {"name": "sync", "attempts": 3, "duration": 1.5, "cost": 12.5, "ok": true, "error": null, "extra": {"a": 1}},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database"]
+    async fn test_postgres_unsupported_type() {
+        let config = postgres("SELECT now() AS time");
+
+        assert!(matches!(
+            config.fetch().await,
+            Err(TargetError::Sql(sqlx::Error::ColumnDecode { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database"]
+    async fn test_postgres_statement_timeout() {
+        let config = postgres("SELECT pg_sleep(1)::text");
+
+        assert!(matches!(
+            config.fetch().await,
+            Err(TargetError::Sql(sqlx::Error::Database(_)))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database"]
+    async fn test_postgres_max_body_size() {
+        let config = Config {
+            max_body_size: Some(100),
+            ..postgres("SELECT n FROM generate_series(1, 10000) AS n")
+        };
+
+        assert!(matches!(
+            config.fetch().await,
+            Err(TargetError::BodyTooLarge(100))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a mysql database"]
+    async fn test_mysql_rows_as_json() {
+        let config = mysql(
+            "SELECT 'sync' AS name, CAST(3 AS SIGNED) AS attempts, CAST(1.5 AS DOUBLE) AS duration, \
+             CAST(12.50 AS DECIMAL(10, 2)) AS cost, NULL AS error",
+        );
+
+        assert_eq!(
+            fetch(&config).await,
+            serde_json::json!([
+                {"name": "sync", "attempts": 3, "duration": 1.5, "cost": 12.5, "error": null},
+            ])
+        );
+    }
+}
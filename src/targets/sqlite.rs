@@ -0,0 +1,133 @@
+use bytes::Bytes;
+use rusqlite::{types::ValueRef, Connection, OpenFlags};
+
+use super::{convert, limit::LimitedJsonArray, TargetError};
+
+#[derive(Debug)]
+pub struct Config {
+    pub path: String,
+    pub query: String,
+    pub max_body_size: Option<u64>,
+}
+
+impl Config {
+    pub fn new(path: String, query: String) -> Self {
+        Config {
+            path,
+            query,
+            max_body_size: None,
+        }
+    }
+
+    /// Runs the query on a read-only connection and returns the rows as a
+    /// JSON array of objects keyed by column name.
+    pub async fn fetch(&self) -> Result<Bytes, TargetError> {
+        let path = self.path.clone();
+        let query = self.query.clone();
+        let limit = self.max_body_size;
+
+        tokio::task::spawn_blocking(move || query_json(&path, &query, limit))
+            .await
+            .map_err(std::io::Error::from)?
+    }
+}
+
+fn query_json(path: &str, query: &str, limit: Option<u64>) -> Result<Bytes, TargetError> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    let mut stmt = conn.prepare(query)?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+    let mut rows = stmt.query([])?;
+    let mut result = LimitedJsonArray::new(limit);
+    while let Some(row) = rows.next()? {
+        let mut object = serde_json::Map::new();
+        for (i, column) in columns.iter().enumerate() {
+            object.insert(column.clone(), value(row.get_ref(i)?));
+        }
+        result.push(&serde_json::Value::Object(object))?;
+    }
+
+    result.into_bytes()
+}
+
+fn value(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => serde_json::Value::from(i),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f)
+            .map_or(serde_json::Value::Null, serde_json::Value::Number),
+        ValueRef::Text(text) | ValueRef::Blob(text) => convert::text(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::testing::TempDir;
+
+    fn database(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+
+        let conn = Connection::open(dir.path("jobs.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE jobs (name TEXT, attempts INTEGER, duration REAL, error TEXT);
+             INSERT INTO jobs VALUES ('sync', 3, 1.5, NULL);
+             INSERT INTO jobs VALUES ('backup', 1, 20.25, 'disk full');",
+        )
+        .unwrap();
+
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_rows_as_json() {
+        let db = database("sqlite-rows");
+        let config = Config::new(
+            db.path("jobs.db"),
+            "SELECT name, attempts, duration, error FROM jobs ORDER BY name".to_owned(),
+        );
+
+        let rows: serde_json::Value =
+            serde_json::from_slice(&config.fetch().await.unwrap()).unwrap();
+        assert_eq!(
+            rows,
+            serde_json::json!([
+                {"name": "backup", "attempts": 1, "duration": 20.25, "error": "disk full"},
+                {"name": "sync", "attempts": 3, "duration": 1.5, "error": null},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let db = database("sqlite-read-only");
+        let config = Config::new(db.path("jobs.db"), "DELETE FROM jobs".to_owned());
+
+        assert!(matches!(config.fetch().await, Err(TargetError::Sqlite(..))));
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let db = database("sqlite-max-body-size");
+        let config = Config {
+            max_body_size: Some(64),
+            ..Config::new(db.path("jobs.db"), "SELECT * FROM jobs".to_owned())
+        };
+
+        assert!(matches!(
+            config.fetch().await,
+            Err(TargetError::BodyTooLarge(64))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_database() {
+        let config = Config::new("does/not/exist.db".to_owned(), "SELECT 1".to_owned());
+
+        assert!(config.fetch().await.is_err());
+    }
+}
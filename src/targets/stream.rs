@@ -0,0 +1,433 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest, http::HeaderValue, protocol::WebSocketConfig, Message,
+};
+use tracing::warn;
+
+use super::TargetError;
+
+type StreamError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Sse,
+    WebSocket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Keep the latest message until a newer one is received.
+    Latest,
+    /// Keep all messages received since the last fetch, joined with newlines.
+    /// The fetch fails with `NoData` if none were received since then.
+    SinceLastScrape,
+}
+
+/// Messages received by the background connection of a stream target.
+#[derive(Debug, Default)]
+pub struct Messages {
+    queue: Mutex<VecDeque<Bytes>>,
+    started: AtomicBool,
+}
+
+/// Target that keeps a connection to a Server-Sent Events or WebSocket
+/// stream open in the background, and reconnects with exponential backoff
+/// when it fails.
+#[derive(Debug)]
+pub struct Config {
+    pub url: String,
+    pub protocol: Protocol,
+    pub headers: HashMap<String, String>,
+    pub mode: Mode,
+    /// Bound for the messages kept in `Mode::SinceLastScrape`, the oldest are
+    /// dropped first.
+    pub max_messages: usize,
+    /// Limit for the size of a single message.
+    pub max_body_size: Option<u64>,
+    /// The connection is reopened when nothing is received for this long.
+    pub idle_timeout: Duration,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub client: reqwest::Client,
+    pub messages: Messages,
+}
+
+impl Config {
+    pub fn new(url: String, protocol: Protocol, client: reqwest::Client) -> Self {
+        Config {
+            url,
+            protocol,
+            headers: HashMap::new(),
+            mode: Mode::Latest,
+            max_messages: 1000,
+            max_body_size: Some(16 << 20),
+            idle_timeout: Duration::from_secs(300),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            client,
+            messages: Messages::default(),
+        }
+    }
+
+    /// Starts the background connection, unless it is already running.
+    pub fn start(self: &Arc<Self>) {
+        if !self.messages.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(self.clone().run());
+        }
+    }
+
+    pub fn fetch(self: &Arc<Self>) -> Result<Bytes, TargetError> {
+        self.start();
+
+        let mut queue = self.messages.queue.lock().unwrap();
+        match self.mode {
+            Mode::Latest => queue.back().cloned().ok_or(TargetError::NoData),
+            Mode::SinceLastScrape if queue.is_empty() => Err(TargetError::NoData),
+            Mode::SinceLastScrape => {
+                let mut data = BytesMut::new();
+                for (i, message) in queue.drain(..).enumerate() {
+                    if i > 0 {
+                        data.extend_from_slice(b"\n");
+                    }
+                    data.extend_from_slice(&message);
+                }
+                Ok(data.freeze())
+            }
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut backoff = self.initial_backoff;
+
+        loop {
+            let result = match self.protocol {
+                Protocol::Sse => self.read_sse(&mut backoff).await,
+                Protocol::WebSocket => self.read_websocket(&mut backoff).await,
+            };
+
+            match result {
+                Ok(()) => warn!("stream {} closed, reconnecting in {:?}", self.url, backoff),
+                Err(err) => warn!(
+                    "stream {} failed, reconnecting in {:?}: {}",
+                    self.url, backoff, err
+                ),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    fn push(&self, message: Bytes) {
+        let mut queue = self.messages.queue.lock().unwrap();
+        if self.mode == Mode::Latest {
+            queue.clear();
+        }
+        queue.push_back(message);
+        while queue.len() > self.max_messages {
+            queue.pop_front();
+        }
+    }
+
+    // Reads events until the stream ends, `backoff` is reset once connected.
+    async fn read_sse(&self, backoff: &mut Duration) -> Result<(), StreamError> {
+        let mut req = self
+            .client
+            .get(&self.url)
+            .header(reqwest::header::ACCEPT, "text/event-stream");
+        for (name, value) in &self.headers {
+            req = req.header(name, value);
+        }
+
+        let mut resp = req.send().await?.error_for_status()?;
+        *backoff = self.initial_backoff;
+
+        let mut events = SseParser::new(self.max_body_size);
+        while let Some(chunk) = self.read(resp.chunk()).await?? {
+            for event in events.feed(&chunk)? {
+                self.push(event);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn read_websocket(&self, backoff: &mut Duration) -> Result<(), StreamError> {
+        let mut request = self.url.as_str().into_client_request()?;
+        for (name, value) in &self.headers {
+            request.headers_mut().insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+
+        let limit = self.max_body_size.map(|limit| limit as usize);
+        let config = WebSocketConfig {
+            max_message_size: limit,
+            max_frame_size: limit,
+            ..WebSocketConfig::default()
+        };
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async_with_config(request, Some(config), false).await?;
+        *backoff = self.initial_backoff;
+
+        while let Some(message) = self.read(socket.next()).await? {
+            match message? {
+                Message::Text(text) => self.push(Bytes::from(text)),
+                Message::Binary(data) => self.push(Bytes::from(data)),
+                Message::Close(_) => break,
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // Waits for the next read, and fails if nothing is received within
+    // `idle_timeout` so that a stalled connection is reopened.
+    async fn read<F: Future>(&self, read: F) -> Result<F::Output, StreamError> {
+        match tokio::time::timeout(self.idle_timeout, read).await {
+            Ok(output) => Ok(output),
+            Err(_) => Err(format!("nothing received for {:?}", self.idle_timeout).into()),
+        }
+    }
+}
+
+/// Splits a Server-Sent Events stream into the data of its events. Fields
+/// other than `data` are ignored.
+struct SseParser {
+    buffer: Vec<u8>,
+    data: Option<Vec<u8>>,
+    /// Limit for the size of an event, and of a line that is not complete
+    /// yet.
+    limit: Option<u64>,
+}
+
+impl SseParser {
+    fn new(limit: Option<u64>) -> Self {
+        SseParser {
+            buffer: Vec::new(),
+            data: None,
+            limit,
+        }
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), StreamError> {
+        match self.limit {
+            Some(limit) if size as u64 > limit => {
+                Err(format!("event larger than {} bytes", limit).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Bytes>, StreamError> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let mut line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+
+            if line.is_empty() {
+                if let Some(data) = self.data.take() {
+                    events.push(Bytes::from(data));
+                }
+                continue;
+            }
+
+            let (field, value) = match line.iter().position(|b| *b == b':') {
+                Some(colon) => (&line[..colon], &line[colon + 1..]),
+                None => (&line[..], &[][..]),
+            };
+            let value = value.strip_prefix(b" ").unwrap_or(value);
+
+            if field == b"data" {
+                let data = self.data.get_or_insert_with(Vec::new);
+                if !data.is_empty() {
+                    data.push(b'\n');
+                }
+                data.extend_from_slice(value);
+                let size = data.len();
+                self.check_size(size)?;
+            }
+        }
+
+        self.check_size(self.buffer.len())?;
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use axum::{
+        extract::ws::{self, WebSocketUpgrade},
+        response::{
+            sse::{Event, Sse},
+            IntoResponse,
+        },
+        routing::get,
+        Extension, Router,
+    };
+
+    use super::*;
+    use crate::targets::{
+        http::ClientConfig,
+        testing::{serve, wait_until},
+    };
+
+    // Sends the events and keeps the stream open.
+    async fn events(events: &'static [&'static str]) -> impl IntoResponse {
+        let events = futures::stream::iter(events.iter())
+            .map(|data| Ok::<_, Infallible>(Event::default().data(*data)))
+            .chain(futures::stream::pending());
+        Sse::new(events)
+    }
+
+    fn config(url: String, protocol: Protocol, mode: Mode) -> Arc<Config> {
+        let config = Arc::new(Config {
+            mode,
+            initial_backoff: Duration::from_millis(10),
+            ..Config::new(url, protocol, ClientConfig::default().build().unwrap())
+        });
+        config.start();
+        config
+    }
+
+    fn received(config: &Config) -> Vec<Bytes> {
+        config
+            .messages
+            .queue
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_sse_latest() {
+        let app = Router::new().route("/", get(|| events(&["1", "2"])));
+        let addr = serve(app).await;
+
+        let config = config(format!("http://{}/", addr), Protocol::Sse, Mode::Latest);
+        wait_until(|| received(&config) == vec![Bytes::from("2")]).await;
+
+        assert_eq!(config.fetch().unwrap(), "2");
+        assert_eq!(config.fetch().unwrap(), "2");
+    }
+
+    #[tokio::test]
+    async fn test_sse_since_last_scrape() {
+        let app = Router::new().route("/", get(|| events(&[r#"{"a": 1}"#, r#"{"a": 2}"#])));
+        let addr = serve(app).await;
+
+        let config = config(
+            format!("http://{}/", addr),
+            Protocol::Sse,
+            Mode::SinceLastScrape,
+        );
+        wait_until(|| received(&config).len() == 2).await;
+
+        assert_eq!(config.fetch().unwrap(), "{\"a\": 1}\n{\"a\": 2}");
+        assert!(matches!(config.fetch(), Err(TargetError::NoData)));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_reconnects() {
+        let connections = Arc::new(AtomicU32::new(0));
+        let handler = |upgrade: WebSocketUpgrade,
+                       Extension(connections): Extension<Arc<AtomicU32>>| async move {
+            upgrade.on_upgrade(move |mut socket| async move {
+                let n = connections.fetch_add(1, Ordering::SeqCst) + 1;
+                let _ = socket.send(ws::Message::Text(n.to_string())).await;
+            })
+        };
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(Extension(connections.clone()));
+        let addr = serve(app).await;
+
+        let config = config(format!("ws://{}/", addr), Protocol::WebSocket, Mode::Latest);
+        wait_until(|| connections.load(Ordering::SeqCst) >= 3).await;
+
+        assert!(config.fetch().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_reconnects() {
+        let connections = Arc::new(AtomicU32::new(0));
+        let handler = |Extension(connections): Extension<Arc<AtomicU32>>| async move {
+            connections.fetch_add(1, Ordering::SeqCst);
+            events(&["1"]).await
+        };
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(Extension(connections.clone()));
+        let addr = serve(app).await;
+
+        let config = Arc::new(Config {
+            idle_timeout: Duration::from_millis(50),
+            initial_backoff: Duration::from_millis(10),
+            ..Config::new(
+                format!("http://{}/", addr),
+                Protocol::Sse,
+                ClientConfig::default().build().unwrap(),
+            )
+        });
+        config.start();
+
+        wait_until(|| connections.load(Ordering::SeqCst) >= 2).await;
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::new(None);
+
+        assert!(parser
+            .feed(b": comment\nevent: status\ndata: {\"a\":")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            parser
+                .feed(b" 1}\r\n\r\ndata: line 1\ndata:line 2\nid: 3\n\n")
+                .unwrap(),
+            vec![Bytes::from("{\"a\": 1}"), Bytes::from("line 1\nline 2")]
+        );
+        assert!(parser.feed(b"event: ping\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sse_parser_limit() {
+        let mut parser = SseParser::new(Some(8));
+        assert_eq!(
+            parser.feed(b"data: 1234\n\n").unwrap(),
+            vec![Bytes::from("1234")]
+        );
+
+        let mut parser = SseParser::new(Some(8));
+        assert!(parser.feed(b"data: 1234\ndata: 5678\n").is_err());
+
+        let mut parser = SseParser::new(Some(8));
+        assert!(parser.feed(b"data: 123456789").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use std::{fs::Metadata, io::SeekFrom};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::Mutex,
+};
+use tracing::warn;
+
+use super::TargetError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Offset {
+    inode: u64,
+    offset: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// End of the data that was collected.
+    committed: Option<Offset>,
+    /// End of the data returned by the last fetch, until it is committed.
+    pending: Option<Offset>,
+}
+
+/// Position up to which a file has been read.
+#[derive(Debug, Default)]
+pub struct Position(Mutex<State>);
+
+impl Position {
+    /// Loads the position saved in `state_file`, a missing or invalid state
+    /// file starts without a position.
+    pub fn load(state_file: &str) -> Self {
+        let offset = match std::fs::read(state_file) {
+            Ok(state) => serde_json::from_slice(&state)
+                .map_err(|e| warn!("ignoring invalid state file {}: {}", state_file, e))
+                .ok(),
+            Err(_) => None,
+        };
+
+        Position(Mutex::new(State {
+            committed: offset,
+            pending: None,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub path: String,
+    /// File the position is saved to after every commit, so that lines are
+    /// not delivered again after a restart.
+    pub state_file: Option<String>,
+    /// Whether to skip the existing content of the file when there is no
+    /// saved position.
+    pub start_at_end: bool,
+    /// Limit for the data delivered by a single fetch, the rest is delivered
+    /// by the following fetches.
+    pub max_body_size: Option<u64>,
+    pub position: Position,
+}
+
+impl Config {
+    pub fn new(path: String) -> Self {
+        Config {
+            path,
+            state_file: None,
+            start_at_end: false,
+            max_body_size: None,
+            position: Position::default(),
+        }
+    }
+
+    /// Returns the complete lines added to the file since the last commit as
+    /// a JSON array of strings, so that a jq stage can count or filter them.
+    /// The file is read from the start again if it was replaced, e.g. by log
+    /// rotation, or truncated.
+    pub async fn fetch(&self) -> Result<Bytes, TargetError> {
+        let mut state = self.position.0.lock().await;
+
+        let mut file = tokio::fs::File::open(&self.path).await?;
+        let metadata = file.metadata().await?;
+        let inode = inode(&metadata);
+
+        let offset = match state.committed {
+            Some(p) if p.inode == inode && p.offset <= metadata.len() => p.offset,
+            // replaced or truncated
+            Some(_) => 0,
+            None => {
+                if self.start_at_end {
+                    metadata.len()
+                } else {
+                    0
+                }
+            }
+        };
+
+        let available = metadata.len() - offset;
+        let limit = self
+            .max_body_size
+            .map_or(available, |max| available.min(max));
+
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(offset)).await?;
+        (&mut file).take(limit).read_to_end(&mut data).await?;
+
+        // an incomplete last line is delivered once it is complete, unless it
+        // does not fit into the limit
+        let end = match data.iter().rposition(|b| *b == b'\n') {
+            Some(newline) => newline + 1,
+            None if limit < available => data.len(),
+            None => 0,
+        };
+        data.truncate(end);
+
+        state.pending = Some(Offset {
+            inode,
+            offset: offset + end as u64,
+        });
+
+        let lines: Vec<_> = String::from_utf8_lossy(&data)
+            .lines()
+            .map(String::from)
+            .collect();
+        Ok(Bytes::from(
+            serde_json::to_vec(&lines).expect("lines are valid json values"),
+        ))
+    }
+
+    /// Moves the position past the data returned by the last fetch, once it
+    /// was collected. Until then, fetches return the same lines again.
+    pub async fn commit(&self) -> Result<(), TargetError> {
+        let mut state = self.position.0.lock().await;
+
+        if let Some(next) = state.pending.take() {
+            if state.committed != Some(next) {
+                self.save(next).await?;
+                state.committed = Some(next);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn save(&self, offset: Offset) -> Result<(), TargetError> {
+        if let Some(state_file) = &self.state_file {
+            let tmp = format!("{}.tmp", state_file);
+            let state = serde_json::to_vec(&offset).expect("offset is a valid json value");
+
+            tokio::fs::write(&tmp, state).await?;
+            tokio::fs::rename(&tmp, state_file).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+// only truncation is detected without inodes
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::targets::testing::TempDir;
+
+    fn log(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("app.log", "");
+        dir
+    }
+
+    fn append(log: &TempDir, data: &str) {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(log.path("app.log"))
+            .unwrap()
+            .write_all(data.as_bytes())
+            .unwrap();
+    }
+
+    async fn fetch(config: &Config) -> Bytes {
+        let data = config.fetch().await.unwrap();
+        config.commit().await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_delivers_new_complete_lines() {
+        let log = log("tail-lines");
+        let config = Config::new(log.path("app.log"));
+
+        append(&log, "error 1\r\nerror 2\nerr");
+        assert_eq!(fetch(&config).await, r#"["error 1","error 2"]"#);
+        assert_eq!(fetch(&config).await, "[]");
+
+        append(&log, "or 3\n");
+        assert_eq!(fetch(&config).await, r#"["error 3"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_uncommitted_lines_are_delivered_again() {
+        let log = log("tail-commit");
+        let config = Config::new(log.path("app.log"));
+
+        append(&log, "error 1\n");
+        assert_eq!(config.fetch().await.unwrap(), r#"["error 1"]"#);
+        assert_eq!(config.fetch().await.unwrap(), r#"["error 1"]"#);
+
+        config.commit().await.unwrap();
+        assert_eq!(config.fetch().await.unwrap(), "[]");
+    }
+
+    #[tokio::test]
+    async fn test_start_at_end() {
+        let log = log("tail-end");
+        append(&log, "old\n");
+
+        let config = Config {
+            start_at_end: true,
+            ..Config::new(log.path("app.log"))
+        };
+
+        assert_eq!(fetch(&config).await, "[]");
+        append(&log, "new\n");
+        assert_eq!(fetch(&config).await, r#"["new"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_and_truncation() {
+        let log = log("tail-rotation");
+        let config = Config::new(log.path("app.log"));
+
+        append(&log, "first\n");
+        assert_eq!(fetch(&config).await, r#"["first"]"#);
+
+        std::fs::rename(log.path("app.log"), log.path("app.log.1")).unwrap();
+        std::fs::write(log.path("app.log"), "rotated\n").unwrap();
+        assert_eq!(fetch(&config).await, r#"["rotated"]"#);
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(log.path("app.log"))
+            .unwrap();
+        append(&log, "x\n");
+        assert_eq!(fetch(&config).await, r#"["x"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let log = log("tail-limit");
+        let config = Config {
+            max_body_size: Some(8),
+            ..Config::new(log.path("app.log"))
+        };
+
+        append(&log, "line 1\nline 2\n");
+        assert_eq!(fetch(&config).await, r#"["line 1"]"#);
+        assert_eq!(fetch(&config).await, r#"["line 2"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_state_file() {
+        let log = log("tail-state");
+        let state_file = log.path("state.json");
+        let config = |state_file: &str| Config {
+            state_file: Some(state_file.to_owned()),
+            position: Position::load(state_file),
+            ..Config::new(log.path("app.log"))
+        };
+
+        append(&log, "before restart\n");
+        assert_eq!(fetch(&config(&state_file)).await, r#"["before restart"]"#);
+
+        append(&log, "after restart\n");
+        assert_eq!(fetch(&config(&state_file)).await, r#"["after restart"]"#);
+    }
+}
@@ -0,0 +1,58 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use axum::Router;
+
+/// Serves `app` on a random local port.
+pub async fn serve(app: Router) -> SocketAddr {
+    let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// Waits up to 5s for `done` to return true, e.g. for a background
+/// connection to receive data.
+pub async fn wait_until<F: Fn() -> bool>(done: F) {
+    for _ in 0..250 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("timed out waiting");
+}
+
+/// Empty directory below the system temp directory, that is removed with
+/// everything in it when dropped. `name` must be unique across all tests.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("data-exporter-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn root(&self) -> String {
+        self.0.to_string_lossy().into_owned()
+    }
+
+    pub fn path(&self, file: &str) -> String {
+        self.0.join(file).to_string_lossy().into_owned()
+    }
+
+    /// Writes `contents` to `file`, creating its parent directories.
+    pub fn write(&self, file: &str, contents: impl AsRef<[u8]>) {
+        let path = self.0.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
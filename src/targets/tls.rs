@@ -0,0 +1,475 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use rustls::{
+    client::{ResolvesClientCert, ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    sign::CertifiedKey,
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName, SignatureScheme,
+};
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("failed to read {0}")]
+    Io(String, #[source] std::io::Error),
+    #[error("no private key found in {0}")]
+    MissingKey(String),
+    #[error("unsupported private key in {0}")]
+    UnsupportedKey(String),
+    #[error("invalid server_name {0}")]
+    InvalidServerName(String, #[source] rustls::client::InvalidDnsNameError),
+    #[error("both cert_file and key_file must be set")]
+    IncompleteClientCert,
+    #[error("invalid tls config")]
+    Rustls(#[from] rustls::Error),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TlsConfig {
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub server_name: Option<String>,
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsConfig {
+    /// Reads the referenced files and builds a rustls client config from them.
+    /// If no `ca_file` is given, the system trust store is used. The files
+    /// are read again for new connections once one of them changed, so that
+    /// rotated certificates are used without a restart.
+    pub fn build(&self) -> Result<ClientConfig, TlsError> {
+        let reloading = Arc::new(Reloading {
+            loaded: Mutex::new(self.load()?),
+            config: self.clone(),
+        });
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(reloading.clone());
+
+        Ok(match self.cert_file {
+            Some(_) => builder.with_client_cert_resolver(reloading),
+            None => builder.with_no_client_auth(),
+        })
+    }
+
+    fn load(&self) -> Result<Loaded, TlsError> {
+        // taken before reading, so that a change while reading is noticed
+        let modified = self.modified();
+
+        let mut roots = RootCertStore::empty();
+        if let Some(path) = &self.ca_file {
+            for cert in read_certs(path)? {
+                roots.add(&cert)?;
+            }
+        } else {
+            let certs = rustls_native_certs::load_native_certs()
+                .map_err(|e| TlsError::Io(String::from("system trust store"), e))?;
+            roots.add_parsable_certificates(&certs.into_iter().map(|c| c.0).collect::<Vec<_>>());
+        }
+
+        let verifier: Arc<dyn ServerCertVerifier> = if self.insecure_skip_verify {
+            Arc::new(SkipVerify)
+        } else {
+            let server_name = match &self.server_name {
+                Some(name) => Some(
+                    ServerName::try_from(name.as_str())
+                        .map_err(|e| TlsError::InvalidServerName(name.clone(), e))?,
+                ),
+                None => None,
+            };
+
+            Arc::new(Verifier {
+                inner: WebPkiVerifier::new(roots, None),
+                server_name,
+            })
+        };
+
+        let client_cert = match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                let key = match rustls::sign::any_supported_type(&read_key(key_file)?) {
+                    Ok(key) => key,
+                    Err(_) => return Err(TlsError::UnsupportedKey(key_file.clone())),
+                };
+                Some(Arc::new(CertifiedKey::new(read_certs(cert_file)?, key)))
+            }
+            (None, None) => None,
+            _ => return Err(TlsError::IncompleteClientCert),
+        };
+
+        Ok(Loaded {
+            modified,
+            verifier,
+            client_cert,
+        })
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [&self.ca_file, &self.cert_file, &self.key_file]
+            .into_iter()
+            .flatten()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// Trust anchors and client certificate read from the files of a `TlsConfig`.
+struct Loaded {
+    modified: Vec<Option<SystemTime>>,
+    verifier: Arc<dyn ServerCertVerifier>,
+    client_cert: Option<Arc<CertifiedKey>>,
+}
+
+/// Verifier and client certificate resolver that read the files of a
+/// `TlsConfig` again when their modification time changed. If they can not
+/// be read, e.g. while they are being replaced, the previous ones are kept.
+struct Reloading {
+    config: TlsConfig,
+    loaded: Mutex<Loaded>,
+}
+
+impl Reloading {
+    fn current(&self) -> (Arc<dyn ServerCertVerifier>, Option<Arc<CertifiedKey>>) {
+        let mut loaded = self.loaded.lock().unwrap();
+
+        if self.config.modified() != loaded.modified {
+            match self.config.load() {
+                Ok(reloaded) => *loaded = reloaded,
+                Err(err) => warn!(
+                    "failed to reload tls files, keeping the previous ones: {}",
+                    err
+                ),
+            }
+        }
+
+        (loaded.verifier.clone(), loaded.client_cert.clone())
+    }
+}
+
+impl ServerCertVerifier for Reloading {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.current().0.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+}
+
+impl ResolvesClientCert for Reloading {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        self.current().1
+    }
+
+    fn has_certs(&self) -> bool {
+        self.config.cert_file.is_some()
+    }
+}
+
+fn read_certs(path: &str) -> Result<Vec<Certificate>, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::Io(path.to_owned(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| TlsError::Io(path.to_owned(), e))?;
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &str) -> Result<PrivateKey, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::Io(path.to_owned(), e))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| TlsError::Io(path.to_owned(), e))?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::MissingKey(path.to_owned()))
+}
+
+/// Verifies the server certificate against the configured trust anchors, and
+/// against `server_name` instead of the host in the url if it is set. The
+/// host in the url is still sent as SNI, since reqwest does not allow
+/// overriding it.
+struct Verifier {
+    inner: WebPkiVerifier,
+    server_name: Option<ServerName>,
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            self.server_name.as_ref().unwrap_or(server_name),
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+}
+
+struct SkipVerify;
+
+impl ServerCertVerifier for SkipVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{routing::get, Router};
+    use axum_server::tls_rustls::RustlsConfig;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use rustls::server::AllowAnyAuthenticatedClient;
+
+    use super::*;
+    use crate::targets::{
+        http::{self, ClientConfig},
+        testing::TempDir,
+        TargetError,
+    };
+
+    struct Pki {
+        dir: TempDir,
+        ca: rcgen::Certificate,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Self {
+            let dir = TempDir::new(&format!("tls-{}", name));
+
+            let mut params = CertificateParams::new(Vec::new());
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = rcgen::Certificate::from_params(params).unwrap();
+            dir.write("ca.pem", ca.serialize_pem().unwrap());
+
+            Pki { dir, ca }
+        }
+
+        /// Issues a certificate signed by the CA, and writes it to `<name>.pem`
+        /// and `<name>-key.pem`.
+        fn issue(&self, name: &str, subject_alt_names: &[&str]) -> (Vec<u8>, Vec<u8>) {
+            let names: Vec<String> = subject_alt_names.iter().map(|n| n.to_string()).collect();
+            let cert = rcgen::Certificate::from_params(CertificateParams::new(names)).unwrap();
+
+            let pem = cert.serialize_pem_with_signer(&self.ca).unwrap();
+            let key = cert.serialize_private_key_pem();
+            self.dir.write(&format!("{}.pem", name), &pem);
+            self.dir.write(&format!("{}-key.pem", name), &key);
+
+            (pem.into_bytes(), key.into_bytes())
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.dir.path(file)
+        }
+    }
+
+    async fn serve(config: RustlsConfig) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "OK" }));
+
+        tokio::spawn(axum_server::from_tcp_rustls(listener, config).serve(app.into_make_service()));
+        addr
+    }
+
+    async fn fetch(addr: SocketAddr, tls: &TlsConfig) -> Result<bytes::Bytes, TargetError> {
+        let client = ClientConfig {
+            tls: Some(tls.build().unwrap()),
+            ..ClientConfig::default()
+        }
+        .build()
+        .unwrap();
+
+        http::Config::new(format!("https://localhost:{}/", addr.port()), client)
+            .fetch(None)
+            .await
+            .map(|body| body.data)
+    }
+
+    #[tokio::test]
+    async fn test_ca_file() {
+        let pki = Pki::new("ca-file");
+        let (cert, key) = pki.issue("server", &["localhost"]);
+        let addr = serve(RustlsConfig::from_pem(cert, key).await.unwrap()).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(pki.path("ca.pem")),
+            ..TlsConfig::default()
+        };
+
+        assert_eq!(fetch(addr, &tls).await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_ca() {
+        let pki = Pki::new("untrusted");
+        let (cert, key) = pki.issue("server", &["localhost"]);
+        let addr = serve(RustlsConfig::from_pem(cert, key).await.unwrap()).await;
+
+        assert!(fetch(addr, &TlsConfig::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_insecure_skip_verify() {
+        let pki = Pki::new("insecure");
+        let (cert, key) = pki.issue("server", &["localhost"]);
+        let addr = serve(RustlsConfig::from_pem(cert, key).await.unwrap()).await;
+
+        let tls = TlsConfig {
+            insecure_skip_verify: true,
+            ..TlsConfig::default()
+        };
+
+        assert_eq!(fetch(addr, &tls).await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_server_name() {
+        let pki = Pki::new("server-name");
+        let (cert, key) = pki.issue("server", &["internal.example.com"]);
+        let addr = serve(RustlsConfig::from_pem(cert, key).await.unwrap()).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(pki.path("ca.pem")),
+            ..TlsConfig::default()
+        };
+        assert!(fetch(addr, &tls).await.is_err());
+
+        let tls = TlsConfig {
+            server_name: Some("internal.example.com".to_owned()),
+            ..tls
+        };
+        assert_eq!(fetch(addr, &tls).await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_client_cert() {
+        let pki = Pki::new("client-cert");
+        let (cert, key) = pki.issue("server", &["localhost"]);
+        pki.issue("client", &["client"]);
+
+        let mut client_roots = RootCertStore::empty();
+        for cert in read_certs(&pki.path("ca.pem")).unwrap() {
+            client_roots.add(&cert).unwrap();
+        }
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(Arc::new(AllowAnyAuthenticatedClient::new(client_roots)))
+            .with_single_cert(
+                vec![Certificate(
+                    rustls_pemfile::certs(&mut cert.as_slice())
+                        .unwrap()
+                        .remove(0),
+                )],
+                PrivateKey(
+                    rustls_pemfile::pkcs8_private_keys(&mut key.as_slice())
+                        .unwrap()
+                        .remove(0),
+                ),
+            )
+            .unwrap();
+        let addr = serve(RustlsConfig::from_config(Arc::new(server_config))).await;
+
+        let tls = TlsConfig {
+            ca_file: Some(pki.path("ca.pem")),
+            ..TlsConfig::default()
+        };
+        assert!(fetch(addr, &tls).await.is_err());
+
+        let tls = TlsConfig {
+            cert_file: Some(pki.path("client.pem")),
+            key_file: Some(pki.path("client-key.pem")),
+            ..tls
+        };
+        assert_eq!(fetch(addr, &tls).await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_reloads_changed_ca_file() {
+        let pki = Pki::new("reload");
+        let (cert, key) = pki.issue("server", &["localhost"]);
+        let addr = serve(RustlsConfig::from_pem(cert, key).await.unwrap()).await;
+
+        let other = Pki::new("reload-other");
+        let tls = TlsConfig {
+            ca_file: Some(other.path("ca.pem")),
+            ..TlsConfig::default()
+        };
+        let client = ClientConfig {
+            tls: Some(tls.build().unwrap()),
+            ..ClientConfig::default()
+        }
+        .build()
+        .unwrap();
+        let config = http::Config::new(format!("https://localhost:{}/", addr.port()), client);
+        assert!(config.fetch(None).await.is_err());
+
+        // a file that can not be parsed keeps the previous trust anchors
+        std::fs::write(
+            other.path("ca.pem"),
+            "-----BEGIN CERTIFICATE-----\nnot base64",
+        )
+        .unwrap();
+        assert!(config.fetch(None).await.is_err());
+
+        std::fs::copy(pki.path("ca.pem"), other.path("ca.pem")).unwrap();
+        assert_eq!(config.fetch(None).await.unwrap().data, "OK");
+    }
+
+    #[test]
+    fn test_incomplete_client_cert() {
+        let tls = TlsConfig {
+            cert_file: Some("client.pem".to_owned()),
+            insecure_skip_verify: true,
+            ..TlsConfig::default()
+        };
+
+        assert!(matches!(tls.build(), Err(TlsError::IncompleteClientCert)));
+    }
+}
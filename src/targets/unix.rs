@@ -0,0 +1,258 @@
+#[cfg(unix)]
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use super::TargetError;
+
+/// Unix domain socket that http requests are sent over instead of connecting
+/// to the host in their url, e.g. `/var/run/docker.sock` for the Docker API.
+/// Only the path and query of the url are used.
+#[derive(Debug)]
+pub struct UnixSocket {
+    pub path: String,
+    #[cfg(unix)]
+    client: hyper::Client<Connector>,
+}
+
+impl UnixSocket {
+    /// Creates a socket whose connections fail after `connect_timeout`, like
+    /// those of the `reqwest::Client` of the target.
+    #[cfg(unix)]
+    pub fn new(path: String, connect_timeout: Option<Duration>) -> Self {
+        UnixSocket {
+            path,
+            client: hyper::Client::builder().build(Connector(connect_timeout)),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(path: String, _connect_timeout: Option<std::time::Duration>) -> Self {
+        UnixSocket { path }
+    }
+
+    /// Sends a request that was built by a `reqwest::Client`, so it carries the
+    /// same headers and auth as requests to tcp targets. The default headers of
+    /// the client are only added when it sends a request itself, so they are
+    /// set here as well. The timeout of the request is not applied, since it
+    /// has to cover reading the body too.
+    #[cfg(unix)]
+    pub async fn execute(
+        &self,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, TargetError> {
+        let url = request.url();
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        };
+
+        let mut builder = hyper::Request::builder()
+            .method(request.method().clone())
+            .uri(hyper::Uri::from(hyperlocal::Uri::new(&self.path, &path)));
+        if let Some(host) = url.host_str() {
+            builder = builder.header(reqwest::header::HOST, host);
+        }
+        for (name, value) in request.headers() {
+            builder = builder.header(name, value);
+        }
+        let headers = request.headers();
+        if !headers.contains_key(reqwest::header::USER_AGENT) {
+            builder = builder.header(reqwest::header::USER_AGENT, super::http::USER_AGENT);
+        }
+        if !headers.contains_key(reqwest::header::ACCEPT) {
+            builder = builder.header(reqwest::header::ACCEPT, "*/*");
+        }
+
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .map_or_else(hyper::Body::empty, |b| hyper::Body::from(b.to_vec()));
+        let resp = self
+            .client
+            .request(
+                builder
+                    .body(body)
+                    .expect("request was already validated by reqwest"),
+            )
+            .await?;
+
+        Ok(reqwest::Response::from(resp))
+    }
+
+    #[cfg(not(unix))]
+    pub async fn execute(
+        &self,
+        _request: reqwest::Request,
+    ) -> Result<reqwest::Response, TargetError> {
+        Err(TargetError::IO(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix sockets are not supported on this platform",
+        )))
+    }
+}
+
+/// Connects to the socket of a request, and fails with `TimedOut` if that
+/// takes longer than the timeout.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+struct Connector(Option<Duration>);
+
+#[cfg(unix)]
+impl hyper::service::Service<hyper::Uri> for Connector {
+    type Response = <hyperlocal::UnixConnector as hyper::service::Service<hyper::Uri>>::Response;
+    type Error = std::io::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: hyper::Uri) -> Self::Future {
+        let connect = hyperlocal::UnixConnector.call(uri);
+        let timeout = self.0;
+
+        Box::pin(async move {
+            match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, connect).await {
+                    Ok(stream) => stream,
+                    Err(_) => Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "timed out connecting to the unix socket",
+                    )),
+                },
+                None => connect.await,
+            }
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::StreamBody,
+        extract::RawQuery,
+        http::{HeaderMap, Uri},
+        routing::any,
+        Router,
+    };
+    use futures::StreamExt;
+    use tokio::net::UnixListener;
+
+    use super::*;
+    use crate::targets::{
+        http::{self, Auth, ClientConfig, Source},
+        testing::TempDir,
+    };
+
+    // Serves `app` on `app.sock` in the returned directory.
+    fn serve(name: &str, app: Router) -> TempDir {
+        let dir = TempDir::new(name);
+        let listener = UnixListener::bind(dir.path("app.sock")).unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let app = app.clone();
+                tokio::spawn(hyper::server::conn::Http::new().serve_connection(stream, app));
+            }
+        });
+        dir
+    }
+
+    fn config(url: &str, socket: String) -> http::Config {
+        http::Config {
+            unix_socket: Some(UnixSocket::new(socket, None)),
+            ..http::Config::new(url.to_owned(), ClientConfig::default().build().unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_over_unix_socket() {
+        let echo = |uri: Uri, RawQuery(query): RawQuery, headers: HeaderMap| async move {
+            let header = |name| {
+                headers
+                    .get(name)
+                    .map(|v| v.to_str().unwrap().to_owned())
+                    .unwrap_or_default()
+            };
+            format!(
+                "{} {} {} {} {} {}",
+                uri.path(),
+                query.unwrap_or_default(),
+                header("host"),
+                header("authorization"),
+                header("user-agent"),
+                header("accept")
+            )
+        };
+        let dir = serve("unix-echo", Router::new().route("/*path", any(echo)));
+
+        let config = http::Config {
+            auth: Some(Auth::Bearer(Source::Content("token".to_owned()))),
+            ..config(
+                "http://docker/v1.41/containers/json?all=true",
+                dir.path("app.sock"),
+            )
+        };
+
+        assert_eq!(
+            config.fetch(None).await.unwrap().data,
+            format!(
+                "/v1.41/containers/json all=true docker Bearer token {} */*",
+                http::USER_AGENT
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timeout_over_unix_socket() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "too late"
+        };
+        let dir = serve("unix-slow", Router::new().route("/", any(slow)));
+
+        let config = http::Config {
+            timeout: Some(Duration::from_millis(100)),
+            ..config("http://localhost/", dir.path("app.sock"))
+        };
+
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::Timeout(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timeout_while_reading_body() {
+        let stalled = || async {
+            let chunks = futures::stream::once(async { Ok::<_, std::io::Error>("partial") })
+                .chain(futures::stream::pending());
+            StreamBody::new(chunks)
+        };
+        let dir = serve("unix-stalled", Router::new().route("/", any(stalled)));
+
+        let config = http::Config {
+            timeout: Some(Duration::from_millis(100)),
+            ..config("http://localhost/", dir.path("app.sock"))
+        };
+
+        assert!(matches!(
+            config.fetch(None).await,
+            Err(TargetError::Timeout(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_missing_socket() {
+        let config = config("http://localhost/", "does/not/exist.sock".to_owned());
+
+        match config.fetch(None).await {
+            Err(TargetError::Socket(err)) => assert!(err.is_connect()),
+            other => panic!("expected connect error, got {:?}", other),
+        }
+    }
+}